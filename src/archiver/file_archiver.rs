@@ -1,4 +1,6 @@
+use std::collections::HashMap;
 use std::io::Read;
+use std::sync::{Arc, Mutex};
 
 use anyhow::{anyhow, Result};
 use indicatif::ProgressBar;
@@ -6,17 +8,58 @@ use indicatif::ProgressBar;
 use crate::backend::{DecryptWriteBackend, ReadSourceOpen};
 use crate::blob::{BlobType, Node, NodeType, Packer, PackerStats};
 use crate::chunker::{ChunkIter, Rabin64};
-use crate::crypto::hash;
+use crate::crypto::{hash, zero_id};
+use crate::id::Id;
 use crate::index::{IndexedBackend, SharedIndexer};
 use crate::repofile::ConfigFile;
 
 use super::{ItemWithParent, ParentResult, TreeItem, TreeType};
 
+// content and size of a file with several hard links, set once the first link has been read
+type LinkContent = Arc<Mutex<Option<(Vec<Id>, u64)>>>;
+
+// contents of files with several hard links, identified by device id and inode.
+// The inner lock is held while the first link is read, so that other links can wait for its content.
+#[derive(Clone, Default)]
+struct HardLinks(Arc<Mutex<HashMap<(u64, u64), LinkContent>>>);
+
+impl HardLinks {
+    // read the file using `read` unless another hard link to it has already been read
+    fn read_once(
+        &self,
+        node: Node,
+        p: &ProgressBar,
+        read: impl FnOnce(Node) -> Result<(Node, u64)>,
+    ) -> Result<(Node, u64)> {
+        let key = match node.hardlink_key() {
+            Some(key) => key,
+            None => return read(node),
+        };
+
+        let link = self.0.lock().unwrap().entry(key).or_default().clone();
+        let mut link = link.lock().unwrap();
+        match &*link {
+            Some((content, size)) => {
+                let mut node = node;
+                node.content = Some(content.clone());
+                p.inc(*size);
+                Ok((node, *size))
+            }
+            None => {
+                let (node, size) = read(node)?;
+                *link = Some((node.content.clone().unwrap_or_default(), size));
+                Ok((node, size))
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct FileArchiver<BE: DecryptWriteBackend, I: IndexedBackend> {
     index: I,
     data_packer: Packer<BE>,
     rabin: Rabin64,
+    hardlinks: HardLinks,
 }
 
 impl<BE: DecryptWriteBackend, I: IndexedBackend> FileArchiver<BE, I> {
@@ -35,6 +78,7 @@ impl<BE: DecryptWriteBackend, I: IndexedBackend> FileArchiver<BE, I> {
             index,
             data_packer,
             rabin,
+            hardlinks: HardLinks::default(),
         })
     }
 
@@ -52,8 +96,7 @@ impl<BE: DecryptWriteBackend, I: IndexedBackend> FileArchiver<BE, I> {
                     p.inc(size);
                     (node, size)
                 } else if let NodeType::File = node.node_type {
-                    let r = open.ok_or(anyhow!("cannot open file"))?.open()?;
-                    self.backup_reader(r, node, p)?
+                    self.backup_file(open, node, p)?
                 } else {
                     (node, 0)
                 };
@@ -62,6 +105,20 @@ impl<BE: DecryptWriteBackend, I: IndexedBackend> FileArchiver<BE, I> {
         })
     }
 
+    // backup a file; the content of files with several hard links is only read once
+    fn backup_file<O: ReadSourceOpen>(
+        &self,
+        open: Option<O>,
+        node: Node,
+        p: ProgressBar,
+    ) -> Result<(Node, u64)> {
+        let read = |node| {
+            let r = open.ok_or(anyhow!("cannot open file"))?.open()?;
+            self.backup_reader(r, node, p.clone())
+        };
+        self.hardlinks.read_once(node, &p, read)
+    }
+
     pub fn backup_reader(
         &self,
         r: impl Read + Send + 'static,
@@ -71,7 +128,12 @@ impl<BE: DecryptWriteBackend, I: IndexedBackend> FileArchiver<BE, I> {
         let chunks: Vec<_> = ChunkIter::new(r, node.meta.size as usize, self.rabin.clone())
             .map(|chunk| {
                 let chunk = chunk?;
-                let id = hash(&chunk);
+                // detect zero chunks (e.g. from sparse files) without hashing them
+                let id = if chunk.iter().all(|b| *b == 0) {
+                    zero_id(chunk.len())
+                } else {
+                    hash(&chunk)
+                };
                 let size = chunk.len() as u64;
 
                 if !self.index.has_data(&id) {
@@ -94,3 +156,45 @@ impl<BE: DecryptWriteBackend, I: IndexedBackend> FileArchiver<BE, I> {
         self.data_packer.finalize()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::Cell;
+
+    use crate::blob::Metadata;
+
+    #[test]
+    fn hardlinks_read_once() -> Result<()> {
+        let hardlinks = HardLinks::default();
+        let p = ProgressBar::hidden();
+        let reads = Cell::new(0);
+        let read_node = |device_id: u64, name: &str| {
+            let meta = Metadata {
+                links: 2,
+                inode: 42,
+                device_id,
+                ..Default::default()
+            };
+            let node = Node::new_node(std::ffi::OsStr::new(name), NodeType::File, meta);
+            hardlinks.read_once(node, &p, |mut node| {
+                reads.set(reads.get() + 1);
+                node.content = Some(vec![Id::random()]);
+                Ok((node, 5))
+            })
+        };
+
+        let (first, _) = read_node(1, "first")?;
+        let (second, size) = read_node(1, "second")?;
+        assert_eq!(reads.get(), 1);
+        assert_eq!(size, 5);
+        assert_eq!(first.content, second.content);
+
+        // without a device id, files on different filesystems could be mixed up
+        read_node(0, "first")?;
+        read_node(0, "second")?;
+        assert_eq!(reads.get(), 3);
+        Ok(())
+    }
+}
@@ -28,7 +28,6 @@ pub struct Archiver<BE: DecryptWriteBackend, I: IndexedBackend> {
     parent_tree: Option<Id>,
     parent: Parent<I>,
     indexer: SharedIndexer<BE>,
-    be: BE,
     snap: SnapshotFile,
 }
 
@@ -48,18 +47,25 @@ impl<BE: DecryptWriteBackend, I: IndexedBackend> Archiver<BE, I> {
 
         let parent = Parent::new(&index, parent_tree, ignore_ctime, ignore_inode);
         let file_archiver = FileArchiver::new(be.clone(), index.clone(), indexer.clone(), config)?;
-        let tree_archiver = TreeArchiver::new(be.clone(), index, indexer.clone(), config, summary)?;
+        let tree_archiver = TreeArchiver::new(be, index, indexer.clone(), config, summary)?;
         Ok(Self {
             file_archiver,
             tree_archiver,
             parent_tree,
             parent,
-            be,
             indexer,
             snap,
         })
     }
 
+    /// Carry over all paths of the parent snapshot which are not contained in the source,
+    /// except the `removed` paths
+    pub fn carry_over_parent(&mut self, removed: &[PathBuf]) {
+        self.parent.set_carry_over(true, removed);
+    }
+
+    /// Archive the given source and return the resulting snapshot.
+    /// All packs and the index are written, but saving the snapshot is left to the caller.
     pub fn archive<R>(
         mut self,
         src: R,
@@ -112,11 +118,11 @@ impl<BE: DecryptWriteBackend, I: IndexedBackend> Archiver<BE, I> {
 
         scope(|scope| -> Result<_> {
             // use parent snapshot
-            iter.filter_map(|item| match self.parent.process(item) {
-                Ok(item) => Some(item),
+            iter.flat_map(|item| match self.parent.process(item) {
+                Ok(items) => items,
                 Err(err) => {
                     warn!("ignoring error reading parent snapshot: {err:?}");
-                    None
+                    Vec::new()
                 }
             })
             // archive files in parallel
@@ -133,6 +139,12 @@ impl<BE: DecryptWriteBackend, I: IndexedBackend> Archiver<BE, I> {
         })
         .unwrap()?;
 
+        for node in self.parent.finish() {
+            let size = node.meta.size;
+            let item = TreeType::Other((PathBuf::new(), node, (ParentResult::Matched(()), size)));
+            self.tree_archiver.add(item)?;
+        }
+
         let stats = self.file_archiver.finalize()?;
         let (id, mut summary) = self.tree_archiver.finalize(self.parent_tree)?;
         stats.apply(&mut summary, BlobType::Data);
@@ -143,9 +155,6 @@ impl<BE: DecryptWriteBackend, I: IndexedBackend> Archiver<BE, I> {
         summary.finalize(self.snap.time)?;
         self.snap.summary = Some(summary);
 
-        let id = self.be.save_file(&self.snap)?;
-        self.snap.id = id;
-
         p.finish_with_message("done");
         Ok(self.snap)
     }
@@ -1,10 +1,12 @@
 use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::ffi::{OsStr, OsString};
+use std::path::{Component, Path, PathBuf};
 
 use anyhow::{anyhow, Result};
 use log::warn;
 
-use crate::blob::{Node, Tree};
+use crate::blob::{Metadata, Node, Tree};
 use crate::id::Id;
 use crate::index::IndexedBackend;
 
@@ -13,10 +15,17 @@ use super::TreeType;
 pub struct Parent<BE: IndexedBackend> {
     tree: Option<Tree>,
     node_idx: usize,
-    stack: Vec<(Option<Tree>, usize)>,
+    // index of the node in tree which matched the last processed item
+    matched: Option<usize>,
+    path: PathBuf,
+    stack: Vec<(Option<Tree>, usize, Option<usize>, PathBuf)>,
     be: BE,
     ignore_ctime: bool,
     ignore_inode: bool,
+    carry_over: bool,
+    carried: Vec<Node>,
+    // paths which must not be carried over, only containing normal components
+    removed: HashSet<PathBuf>,
 }
 
 #[derive(Clone, Debug)]
@@ -54,13 +63,26 @@ impl<BE: IndexedBackend> Parent<BE> {
         Self {
             tree,
             node_idx: 0,
+            matched: None,
+            path: PathBuf::new(),
             stack: Vec::new(),
             be: be.clone(),
             ignore_ctime,
             ignore_inode,
+            carry_over: false,
+            carried: Vec::new(),
+            removed: HashSet::new(),
         }
     }
 
+    /// Carry over all nodes of the parent which are not contained in the processed items,
+    /// except the `removed` paths. This is used if only some paths are read, e.g. when backing
+    /// up a list of files.
+    pub fn set_carry_over(&mut self, carry_over: bool, removed: &[PathBuf]) {
+        self.carry_over = carry_over;
+        self.removed = removed.iter().map(|path| normalize(path)).collect();
+    }
+
     pub fn p_node(&mut self, name: &OsStr) -> Option<&Node> {
         match &self.tree {
             None => None,
@@ -70,8 +92,17 @@ impl<BE: IndexedBackend> Parent<BE> {
                     match p_nodes.get(self.node_idx) {
                         None => break None,
                         Some(p_node) => match p_node.name().as_os_str().cmp(name) {
-                            Ordering::Less => self.node_idx += 1,
+                            Ordering::Less => {
+                                if self.carry_over
+                                    && self.matched != Some(self.node_idx)
+                                    && !is_removed(&self.removed, &self.path, p_node)
+                                {
+                                    self.carried.push(p_node.clone());
+                                }
+                                self.node_idx += 1;
+                            }
                             Ordering::Equal => {
+                                self.matched = Some(self.node_idx);
                                 break Some(p_node);
                             }
                             Ordering::Greater => {
@@ -125,31 +156,72 @@ impl<BE: IndexedBackend> Parent<BE> {
             },
             None => None,
         };
-        self.stack.push((self.tree.take(), self.node_idx));
+        self.stack.push((
+            self.tree.take(),
+            self.node_idx,
+            self.matched.take(),
+            self.path.clone(),
+        ));
         self.tree = tree;
         self.node_idx = 0;
         Ok(())
     }
 
     pub fn finish_dir(&mut self) -> Result<()> {
-        let (tree, node_idx) = self
+        self.carry_remaining();
+        let (tree, node_idx, matched, path) = self
             .stack
             .pop()
             .ok_or_else(|| anyhow!("tree stack empty??"))?;
 
         self.tree = tree;
         self.node_idx = node_idx;
+        self.matched = matched;
+        self.path = path;
 
         Ok(())
     }
 
-    pub fn process<O>(&mut self, item: TreeType<O, OsString>) -> Result<ItemWithParent<O>> {
+    // carry over all nodes of the current tree which have not been processed
+    fn carry_remaining(&mut self) {
+        if !self.carry_over {
+            return;
+        }
+        if let Some(tree) = &self.tree {
+            for (idx, node) in tree.nodes.iter().enumerate().skip(self.node_idx) {
+                if self.matched != Some(idx) && !is_removed(&self.removed, &self.path, node) {
+                    self.carried.push(node.clone());
+                }
+            }
+        }
+    }
+
+    /// Finish the root tree and return the nodes which need to be carried over
+    pub fn finish(&mut self) -> Vec<Node> {
+        self.carry_remaining();
+        std::mem::take(&mut self.carried)
+    }
+
+    /// Process the item and return it together with all nodes which are carried over before it
+    pub fn process<O: Default>(
+        &mut self,
+        item: TreeType<O, OsString>,
+    ) -> Result<Vec<ItemWithParent<O>>> {
+        // carried over nodes are located in the current dir, which changes when processing a tree
+        let path = self.path.clone();
         let result = match item {
-            TreeType::NewTree((path, node, tree)) => {
+            TreeType::NewTree((path, mut node, tree)) => {
+                // directories which are not contained in the source keep their metadata
+                if self.carry_over && node.meta == Metadata::default() {
+                    if let Some(p_node) = self.p_node(&tree) {
+                        node.meta = p_node.meta.clone();
+                    }
+                }
                 let parent_result = self
                     .is_parent(&node, &tree)
                     .map(|node| node.subtree.unwrap());
                 self.set_dir(&tree)?;
+                self.path = path.clone();
                 TreeType::NewTree((path, node, parent_result))
             }
             TreeType::EndTree => {
@@ -176,6 +248,30 @@ impl<BE: IndexedBackend> Parent<BE> {
                 TreeType::Other((path, node, (open, parent)))
             }
         };
-        Ok(result)
+
+        let mut items: Vec<_> = self
+            .carried
+            .drain(..)
+            .map(|node| {
+                TreeType::Other((
+                    path.clone(),
+                    node,
+                    (O::default(), ParentResult::Matched(())),
+                ))
+            })
+            .collect();
+        items.push(result);
+        Ok(items)
     }
 }
+
+/// Only keep the normal components of a path, i.e. remove root dirs and prefixes
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|comp| matches!(comp, Component::Normal(_)))
+        .collect()
+}
+
+fn is_removed(removed: &HashSet<PathBuf>, path: &Path, node: &Node) -> bool {
+    !removed.is_empty() && removed.contains(&normalize(&path.join(node.name())))
+}
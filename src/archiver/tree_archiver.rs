@@ -78,6 +78,13 @@ impl<BE: DecryptWriteBackend, I: IndexedBackend> TreeArchiver<BE, I> {
 
     pub fn add_file(&mut self, path: &Path, node: Node, parent: ParentResult<()>, size: u64) {
         let filename = path.join(node.name());
+        // directories which are carried over from the parent
+        if node.is_dir() {
+            debug!("unchanged tree: {:?}", filename);
+            self.summary.dirs_unmodified += 1;
+            self.tree.add(node);
+            return;
+        }
         match parent {
             ParentResult::Matched(_) => {
                 debug!("unchanged file: {:?}", filename);
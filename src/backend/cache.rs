@@ -1,11 +1,14 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::fs::{self, File};
 use std::io::{Read, Seek, SeekFrom, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
 use anyhow::{anyhow, Result};
 use bytes::Bytes;
 use dirs::cache_dir;
+use filetime::{set_file_atime, set_file_mtime, FileTime};
 use log::*;
 use walkdir::WalkDir;
 
@@ -68,26 +71,37 @@ impl<BE: WriteBackend> ReadBackend for CachedBackend<BE> {
         offset: u32,
         length: u32,
     ) -> Result<Bytes> {
-        match (&self.cache, cacheable || tpe.is_cacheable()) {
-            (None, _) | (Some(_), false) => {
-                self.be.read_partial(tpe, id, cacheable, offset, length)
+        match &self.cache {
+            // data packs are not cached completely, but only the ranges which are read
+            Some(cache) if !cacheable && tpe == FileType::Pack && cache.cache_data => {
+                match cache.read_range(id, offset, length) {
+                    Ok(res) => Ok(res),
+                    _ => {
+                        let data = self.be.read_partial(tpe, id, cacheable, offset, length)?;
+                        let _ = cache.write_range(id, offset, length, data.clone());
+                        Ok(data)
+                    }
+                }
             }
-            (Some(cache), true) => match cache.read_partial(tpe, id, offset, length) {
-                Ok(res) => Ok(res),
-                _ => match self.be.read_full(tpe, id) {
-                    // read full file, save to cache and return partial content from cache
-                    // TODO: - Do not read to memory, but use a Reader
-                    //       - Don't read from cache, but use the right part of the read content
-                    Ok(data) => {
-                        if cache.write_bytes(tpe, id, data).is_ok() {
-                            cache.read_partial(tpe, id, offset, length)
-                        } else {
-                            self.be.read_partial(tpe, id, false, offset, length)
+            Some(cache) if cacheable || tpe.is_cacheable() => {
+                match cache.read_partial(tpe, id, offset, length) {
+                    Ok(res) => Ok(res),
+                    _ => match self.be.read_full(tpe, id) {
+                        // read full file, save to cache and return partial content from cache
+                        // TODO: - Do not read to memory, but use a Reader
+                        //       - Don't read from cache, but use the right part of the read content
+                        Ok(data) => {
+                            if cache.write_bytes(tpe, id, data).is_ok() {
+                                cache.read_partial(tpe, id, offset, length)
+                            } else {
+                                self.be.read_partial(tpe, id, false, offset, length)
+                            }
                         }
-                    }
-                    error => error,
-                },
-            },
+                        error => error,
+                    },
+                }
+            }
+            _ => self.be.read_partial(tpe, id, cacheable, offset, length),
         }
     }
 }
@@ -111,28 +125,161 @@ impl<BE: WriteBackend> WriteBackend for CachedBackend<BE> {
             if cacheable || tpe.is_cacheable() {
                 let _ = cache.remove(tpe, id);
             }
+            if tpe == FileType::Pack && cache.cache_data {
+                let _ = cache.remove_ranges(id);
+            }
         }
         self.be.remove(tpe, id, cacheable)
     }
 }
 
+/// In-memory index of all cached files, shared by all clones of a [`Cache`].
+/// It is only maintained if the cache size is limited.
+#[derive(Default)]
+struct CacheIndex {
+    // total size of all cached files
+    size: u64,
+    // size, last access and whether the file has been used by the running command
+    files: HashMap<PathBuf, (u64, FileTime, bool)>,
+    // all cached files ordered by their last access
+    lru: BTreeSet<(FileTime, PathBuf)>,
+}
+
+impl CacheIndex {
+    fn insert(&mut self, path: PathBuf, size: u64, atime: FileTime, used: bool) {
+        let _ = self.remove(&path);
+        self.size += size;
+        let _ = self.lru.insert((atime, path.clone()));
+        let _ = self.files.insert(path, (size, atime, used));
+    }
+
+    fn remove(&mut self, path: &Path) -> Option<u64> {
+        let (size, atime, _) = self.files.remove(path)?;
+        let _ = self.lru.remove(&(atime, path.to_path_buf()));
+        self.size -= size;
+        Some(size)
+    }
+
+    fn touch(&mut self, path: &Path, atime: FileTime) {
+        if let Some((_, old_atime, used)) = self.files.get_mut(path) {
+            let _ = self.lru.remove(&(*old_atime, path.to_path_buf()));
+            let _ = self.lru.insert((atime, path.to_path_buf()));
+            *old_atime = atime;
+            *used = true;
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Cache {
     path: PathBuf,
+    max_size: Option<u64>,
+    cache_data: bool,
+    index: Arc<Mutex<CacheIndex>>,
 }
 
 impl Cache {
-    pub fn new(id: Id, path: Option<PathBuf>) -> Result<Self> {
-        let mut path = path.unwrap_or({
-            let mut dir = cache_dir().ok_or_else(|| anyhow!("no cache dir"))?;
-            dir.push("rustic");
-            dir
-        });
+    /// Open the cache for the repository with the given id. If `max_size` is given, the least
+    /// recently used files are evicted when the cache grows larger.
+    /// If `cache_data` is set, also ranges read from data packs are cached.
+    pub fn new(
+        id: Id,
+        path: Option<PathBuf>,
+        max_size: Option<u64>,
+        cache_data: bool,
+    ) -> Result<Self> {
+        let mut path = base_dir(path)?;
         fs::create_dir_all(&path)?;
         cachedir::ensure_tag(&path)?;
         path.push(id.to_hex());
         fs::create_dir_all(&path)?;
-        Ok(Self { path })
+        // the modification time of the directory marks the last use of the cache
+        set_file_mtime(&path, FileTime::now())?;
+
+        let cache = Self {
+            path,
+            max_size,
+            cache_data,
+            index: Arc::default(),
+        };
+        if let Some(max_size) = max_size {
+            // the cache dir is only read once; afterwards the index is kept up to date
+            let mut index = cache.index.lock().unwrap();
+            for (path, len, atime) in cache.files() {
+                index.insert(path, len, atime, false);
+            }
+            Self::evict(&mut index, max_size);
+        }
+        Ok(cache)
+    }
+
+    /// All files in the cache together with their size and last access time
+    fn files(&self) -> impl Iterator<Item = (PathBuf, u64, FileTime)> {
+        WalkDir::new(&self.path)
+            .into_iter()
+            .filter_map(walkdir::Result::ok)
+            .filter(|e| e.file_type().is_file())
+            .filter_map(|e| {
+                let meta = e.metadata().ok()?;
+                let atime = FileTime::from_last_access_time(&meta);
+                Some((e.into_path(), meta.len(), atime))
+            })
+    }
+
+    /// Mark the file as used. The access time is set explicitly as file systems
+    /// are often mounted with noatime or relatime.
+    fn touch(&self, path: &Path) {
+        if self.max_size.is_some() {
+            let now = FileTime::now();
+            let _ = set_file_atime(path, now);
+            self.index.lock().unwrap().touch(path, now);
+        }
+    }
+
+    fn added(&self, path: &Path, size: u64) {
+        if let Some(max_size) = self.max_size {
+            let _ = set_file_atime(path, FileTime::now());
+            let mut index = self.index.lock().unwrap();
+            index.insert(path.to_path_buf(), size, FileTime::now(), true);
+            if index.size > max_size {
+                Self::evict(&mut index, max_size);
+            }
+        }
+    }
+
+    fn removed(&self, path: &Path) {
+        if self.max_size.is_some() {
+            let _ = self.index.lock().unwrap().remove(path);
+        }
+    }
+
+    /// Remove least recently used files until the cache size is within its limit.
+    /// Files used by the running command are never removed.
+    fn evict(index: &mut CacheIndex, max_size: u64) {
+        let mut size = index.size;
+        let mut evict = Vec::new();
+        for (_, path) in &index.lru {
+            if size <= max_size {
+                break;
+            }
+            let (len, _, used) = index.files[path];
+            if !used {
+                evict.push(path.clone());
+                size -= len;
+            }
+        }
+        for path in evict {
+            debug!("evicting {path:?} from cache");
+            if fs::remove_file(&path).is_ok() || !path.exists() {
+                let _ = index.remove(&path);
+            }
+        }
+        if index.size > max_size {
+            debug!(
+                "cache size {} exceeds the limit, but all remaining files are in use",
+                index.size
+            );
+        }
     }
 
     pub fn location(&self) -> &str {
@@ -197,8 +344,10 @@ impl Cache {
 
     pub fn read_full(&self, tpe: FileType, id: &Id) -> Result<Bytes> {
         trace!("cache reading tpe: {:?}, id: {}", &tpe, &id);
-        let data = fs::read(self.path(tpe, id))?;
+        let filename = self.path(tpe, id);
+        let data = fs::read(&filename)?;
         trace!("cache hit!");
+        self.touch(&filename);
         Ok(data.into())
     }
 
@@ -209,30 +358,169 @@ impl Cache {
             &id,
             &offset
         );
-        let mut file = File::open(self.path(tpe, id))?;
+        let filename = self.path(tpe, id);
+        let mut file = File::open(&filename)?;
         file.seek(SeekFrom::Start(u64::from(offset)))?;
         let mut vec = vec![0; length as usize];
         file.read_exact(&mut vec)?;
         trace!("cache hit!");
+        self.touch(&filename);
         Ok(vec.into())
     }
 
     fn write_bytes(&self, tpe: FileType, id: &Id, buf: Bytes) -> Result<()> {
         trace!("cache writing tpe: {:?}, id: {}", &tpe, &id);
         fs::create_dir_all(self.dir(tpe, id))?;
-        let filename = self.path(tpe, id);
+        self.write_file(&self.path(tpe, id), &buf)
+    }
+
+    fn write_file(&self, filename: &Path, buf: &[u8]) -> Result<()> {
         let mut file = fs::OpenOptions::new()
             .create(true)
             .write(true)
+            .truncate(true)
             .open(filename)?;
-        file.write_all(&buf)?;
+        file.write_all(buf)?;
+        self.added(filename, buf.len().try_into()?);
         Ok(())
     }
 
     fn remove(&self, tpe: FileType, id: &Id) -> Result<()> {
         trace!("cache writing tpe: {:?}, id: {}", &tpe, &id);
-        let filename = self.path(tpe, id);
+        self.remove_file(&self.path(tpe, id))
+    }
+
+    fn remove_file(&self, filename: &Path) -> Result<()> {
         fs::remove_file(filename)?;
+        self.removed(filename);
+        Ok(())
+    }
+
+    fn range_path(&self, id: &Id, offset: u32, length: u32) -> PathBuf {
+        let hex_id = id.to_hex();
+        self.path
+            .join("ranges")
+            .join(&hex_id[0..2])
+            .join(format!("{}-{offset}-{length}", hex_id.as_str()))
+    }
+
+    fn read_range(&self, id: &Id, offset: u32, length: u32) -> Result<Bytes> {
+        trace!("cache reading range of pack {id}, offset: {offset}, length: {length}");
+        let filename = self.range_path(id, offset, length);
+        let data = fs::read(&filename)?;
+        trace!("cache hit!");
+        self.touch(&filename);
+        Ok(data.into())
+    }
+
+    fn write_range(&self, id: &Id, offset: u32, length: u32, buf: Bytes) -> Result<()> {
+        trace!("cache writing range of pack {id}, offset: {offset}, length: {length}");
+        let filename = self.range_path(id, offset, length);
+        fs::create_dir_all(filename.parent().unwrap())?;
+        self.write_file(&filename, &buf)
+    }
+
+    fn remove_ranges(&self, id: &Id) -> Result<()> {
+        let hex_id = id.to_hex();
+        let dir = self.path.join("ranges").join(&hex_id[0..2]);
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with(hex_id.as_str()) {
+                self.remove_file(&entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the directory containing the caches of all repositories
+pub fn base_dir(path: Option<PathBuf>) -> Result<PathBuf> {
+    Ok(match path {
+        Some(path) => path,
+        None => {
+            let mut dir = cache_dir().ok_or_else(|| anyhow!("no cache dir"))?;
+            dir.push("rustic");
+            dir
+        }
+    })
+}
+
+/// The cache directory of a repository
+pub struct CacheDir {
+    pub path: PathBuf,
+    pub last_used: SystemTime,
+}
+
+impl CacheDir {
+    /// List the caches of all repositories within `base`.
+    /// If `base` is not tagged as cache directory, no caches are returned.
+    pub fn list(base: &Path) -> Result<Vec<Self>> {
+        if !base.exists() || !cachedir::is_tagged(base)? {
+            return Ok(Vec::new());
+        }
+
+        let mut dirs = Vec::new();
+        for entry in fs::read_dir(base)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir()
+                || Id::from_hex(&entry.file_name().to_string_lossy()).is_err()
+            {
+                continue;
+            }
+            dirs.push(Self {
+                path: entry.path(),
+                last_used: entry.metadata()?.modified()?,
+            });
+        }
+        Ok(dirs)
+    }
+
+    /// List the caches within `base` which have not been used for longer than `max_age`
+    pub fn list_old(base: &Path, max_age: Duration) -> Result<Vec<Self>> {
+        let now = SystemTime::now();
+        Ok(Self::list(base)?
+            .into_iter()
+            .filter(|dir| now.duration_since(dir.last_used).unwrap_or_default() > max_age)
+            .collect())
+    }
+
+    /// The total size of all files in the cache
+    pub fn size(&self) -> u64 {
+        WalkDir::new(&self.path)
+            .into_iter()
+            .filter_map(walkdir::Result::ok)
+            .filter_map(|e| e.metadata().ok())
+            .filter(fs::Metadata::is_file)
+            .map(|m| m.len())
+            .sum()
+    }
+
+    pub fn remove(&self) -> Result<()> {
+        Ok(fs::remove_dir_all(&self.path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evict_unused_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let repo_id = Id::random();
+        let (id1, id2, id3) = (Id::random(), Id::random(), Id::random());
+
+        let cache = Cache::new(repo_id, Some(dir.path().into()), Some(20), false)?;
+        cache.write_bytes(FileType::Snapshot, &id1, Bytes::from_static(b"12345678"))?;
+        cache.write_bytes(FileType::Snapshot, &id2, Bytes::from_static(b"12345678"))?;
+
+        // reopen the cache, only files used by the running command are kept
+        let cache = Cache::new(repo_id, Some(dir.path().into()), Some(20), false)?;
+        assert!(cache.read_full(FileType::Snapshot, &id2).is_ok());
+        cache.write_bytes(FileType::Snapshot, &id3, Bytes::from_static(b"12345678"))?;
+        assert!(cache.read_full(FileType::Snapshot, &id1).is_err());
+        assert!(cache.read_full(FileType::Snapshot, &id2).is_ok());
+        assert!(cache.read_full(FileType::Snapshot, &id3).is_ok());
         Ok(())
     }
 }
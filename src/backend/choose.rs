@@ -1,17 +1,25 @@
+use std::path::PathBuf;
+
 use anyhow::{bail, Result};
 use bytes::Bytes;
 
 use super::{FileType, Id, ReadBackend, WriteBackend};
-use super::{LocalBackend, RcloneBackend, RestBackend};
+use super::{
+    LocalBackend, MemoryBackend, MirrorBackend, RcloneBackend, RestBackend, S3Backend, SftpBackend,
+};
 
 #[derive(Clone)]
 pub enum ChooseBackend {
     Local(LocalBackend),
+    Memory(MemoryBackend),
+    Mirror(MirrorBackend<ChooseBackend>),
     Rest(RestBackend),
     Rclone(RcloneBackend),
+    S3(S3Backend),
+    Sftp(SftpBackend),
 }
 
-use ChooseBackend::{Local, Rclone, Rest};
+use ChooseBackend::{Local, Memory, Mirror, Rclone, Rest, Sftp, S3};
 
 impl ChooseBackend {
     pub fn from_url(url: &str) -> Result<Self> {
@@ -21,6 +29,13 @@ impl ChooseBackend {
             Some(("rclone", path)) => Rclone(RcloneBackend::new(path)?),
             Some(("rest", path)) => Rest(RestBackend::new(path)?),
             Some(("local", path)) => Local(LocalBackend::new(path)?),
+            Some(("memory", name)) => Memory(MemoryBackend::new(name)),
+            // members of a mirror are separated by '|'
+            Some(("mirror", urls)) => Mirror(MirrorBackend::new(
+                urls.split('|').map(Self::from_url).collect::<Result<_>>()?,
+            )?),
+            Some(("s3", path)) => S3(S3Backend::new(path)?),
+            Some(("sftp", path)) => Sftp(SftpBackend::new(path)?),
             Some((backend, _)) => bail!("backend {backend} is not supported!"),
             None => Local(LocalBackend::new(url)?),
         })
@@ -31,32 +46,48 @@ impl ReadBackend for ChooseBackend {
     fn location(&self) -> String {
         match self {
             Local(local) => local.location(),
+            Memory(memory) => memory.location(),
+            Mirror(mirror) => mirror.location(),
             Rest(rest) => rest.location(),
             Rclone(rclone) => rclone.location(),
+            S3(s3) => s3.location(),
+            Sftp(sftp) => sftp.location(),
         }
     }
 
     fn set_option(&mut self, option: &str, value: &str) -> Result<()> {
         match self {
             Local(local) => local.set_option(option, value),
+            Memory(memory) => memory.set_option(option, value),
+            Mirror(mirror) => mirror.set_option(option, value),
             Rest(rest) => rest.set_option(option, value),
             Rclone(rclone) => rclone.set_option(option, value),
+            S3(s3) => s3.set_option(option, value),
+            Sftp(sftp) => sftp.set_option(option, value),
         }
     }
 
     fn list_with_size(&self, tpe: FileType) -> Result<Vec<(Id, u32)>> {
         match self {
             Local(local) => local.list_with_size(tpe),
+            Memory(memory) => memory.list_with_size(tpe),
+            Mirror(mirror) => mirror.list_with_size(tpe),
             Rest(rest) => rest.list_with_size(tpe),
             Rclone(rclone) => rclone.list_with_size(tpe),
+            S3(s3) => s3.list_with_size(tpe),
+            Sftp(sftp) => sftp.list_with_size(tpe),
         }
     }
 
     fn read_full(&self, tpe: FileType, id: &Id) -> Result<Bytes> {
         match self {
             Local(local) => local.read_full(tpe, id),
+            Memory(memory) => memory.read_full(tpe, id),
+            Mirror(mirror) => mirror.read_full(tpe, id),
             Rest(rest) => rest.read_full(tpe, id),
             Rclone(rclone) => rclone.read_full(tpe, id),
+            S3(s3) => s3.read_full(tpe, id),
+            Sftp(sftp) => sftp.read_full(tpe, id),
         }
     }
 
@@ -70,8 +101,31 @@ impl ReadBackend for ChooseBackend {
     ) -> Result<Bytes> {
         match self {
             Local(local) => local.read_partial(tpe, id, cacheable, offset, length),
+            Memory(memory) => memory.read_partial(tpe, id, cacheable, offset, length),
+            Mirror(mirror) => mirror.read_partial(tpe, id, cacheable, offset, length),
             Rest(rest) => rest.read_partial(tpe, id, cacheable, offset, length),
             Rclone(rclone) => rclone.read_partial(tpe, id, cacheable, offset, length),
+            S3(s3) => s3.read_partial(tpe, id, cacheable, offset, length),
+            Sftp(sftp) => sftp.read_partial(tpe, id, cacheable, offset, length),
+        }
+    }
+
+    fn list_orphaned(&self) -> Result<Vec<PathBuf>> {
+        match self {
+            Local(local) => local.list_orphaned(),
+            Memory(memory) => memory.list_orphaned(),
+            Mirror(mirror) => mirror.list_orphaned(),
+            Rest(rest) => rest.list_orphaned(),
+            Rclone(rclone) => rclone.list_orphaned(),
+            S3(s3) => s3.list_orphaned(),
+            Sftp(sftp) => sftp.list_orphaned(),
+        }
+    }
+
+    fn list_divergence(&self, tpe: FileType) -> Result<Vec<String>> {
+        match self {
+            Mirror(mirror) => mirror.list_divergence(tpe),
+            _ => Ok(Vec::new()),
         }
     }
 }
@@ -80,24 +134,36 @@ impl WriteBackend for ChooseBackend {
     fn create(&self) -> Result<()> {
         match self {
             Local(local) => local.create(),
+            Memory(memory) => memory.create(),
+            Mirror(mirror) => mirror.create(),
             Rest(rest) => rest.create(),
             Rclone(rclone) => rclone.create(),
+            S3(s3) => s3.create(),
+            Sftp(sftp) => sftp.create(),
         }
     }
 
     fn write_bytes(&self, tpe: FileType, id: &Id, cacheable: bool, buf: Bytes) -> Result<()> {
         match self {
             Local(local) => local.write_bytes(tpe, id, cacheable, buf),
+            Memory(memory) => memory.write_bytes(tpe, id, cacheable, buf),
+            Mirror(mirror) => mirror.write_bytes(tpe, id, cacheable, buf),
             Rest(rest) => rest.write_bytes(tpe, id, cacheable, buf),
             Rclone(rclone) => rclone.write_bytes(tpe, id, cacheable, buf),
+            S3(s3) => s3.write_bytes(tpe, id, cacheable, buf),
+            Sftp(sftp) => sftp.write_bytes(tpe, id, cacheable, buf),
         }
     }
 
     fn remove(&self, tpe: FileType, id: &Id, cacheable: bool) -> Result<()> {
         match self {
             Local(local) => local.remove(tpe, id, cacheable),
+            Memory(memory) => memory.remove(tpe, id, cacheable),
+            Mirror(mirror) => mirror.remove(tpe, id, cacheable),
             Rest(rest) => rest.remove(tpe, id, cacheable),
             Rclone(rclone) => rclone.remove(tpe, id, cacheable),
+            S3(s3) => s3.remove(tpe, id, cacheable),
+            Sftp(sftp) => sftp.remove(tpe, id, cacheable),
         }
     }
 }
@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use anyhow::Result;
 use bytes::Bytes;
 
@@ -50,6 +52,18 @@ impl<BE: WriteBackend> ReadBackend for HotColdBackend<BE> {
             (Some(be), true) => be.read_partial(tpe, id, cacheable, offset, length),
         }
     }
+
+    fn list_orphaned(&self) -> Result<Vec<PathBuf>> {
+        let mut files = self.be.list_orphaned()?;
+        if let Some(be) = &self.hot_be {
+            files.extend(be.list_orphaned()?);
+        }
+        Ok(files)
+    }
+
+    fn list_divergence(&self, tpe: FileType) -> Result<Vec<String>> {
+        self.be.list_divergence(tpe)
+    }
 }
 
 impl<BE: WriteBackend> WriteBackend for HotColdBackend<BE> {
@@ -1,4 +1,5 @@
-use std::fs::{read_link, File};
+use std::fs::{read_link, symlink_metadata, File};
+use std::io::ErrorKind;
 #[cfg(not(windows))]
 use std::os::unix::fs::{FileTypeExt, MetadataExt};
 use std::path::{Path, PathBuf};
@@ -9,7 +10,7 @@ use bytesize::ByteSize;
 use chrono::TimeZone;
 use chrono::{DateTime, Local, Utc};
 use clap::Parser;
-use ignore::{overrides::OverrideBuilder, DirEntry, Walk, WalkBuilder};
+use ignore::{overrides::OverrideBuilder, Walk, WalkBuilder};
 use log::*;
 use merge::Merge;
 use serde::Deserialize;
@@ -206,8 +207,11 @@ impl Iterator for LocalSource {
             item => item,
         }
         .map(|e| {
+            let e = e?;
+            let m = e.metadata()?;
             map_entry(
-                e?,
+                e.into_path(),
+                m,
                 self.save_opts.with_atime,
                 self.save_opts.ignore_devid,
                 #[cfg(not(windows))]
@@ -217,14 +221,115 @@ impl Iterator for LocalSource {
     }
 }
 
+/// [`FilesSource`] yields the given files and directories. In contrast to [`LocalSource`],
+/// directories are not walked, i.e. only the directory itself is contained.
+pub struct FilesSource {
+    paths: std::vec::IntoIter<PathBuf>,
+    removed: Vec<PathBuf>,
+    save_opts: LocalSourceSaveOptions,
+    #[cfg(not(windows))]
+    cache: UsersCache,
+}
+
+impl FilesSource {
+    /// Use the given absolute `paths` which are contained in one of the `roots`.
+    /// Paths which don't exist are not read but reported as removed. Instead, their nearest
+    /// existing parent directory is read.
+    pub fn new(save_opts: LocalSourceSaveOptions, paths: Vec<PathBuf>, roots: &[PathBuf]) -> Self {
+        let contained = |path: &Path| roots.iter().any(|root| path.starts_with(root));
+        let mut existing = Vec::new();
+        let mut removed = Vec::new();
+        for path in paths {
+            if !contained(&path) {
+                warn!("ignoring {path:?}: not contained in the backup sources");
+                continue;
+            }
+            match symlink_metadata(&path) {
+                Err(err) if err.kind() == ErrorKind::NotFound => {
+                    // remove the topmost missing directory
+                    let mut gone = path.as_path();
+                    while let Some(parent) = gone.parent() {
+                        if !contained(parent) || parent.file_name().is_none() {
+                            break;
+                        }
+                        if symlink_metadata(parent).is_ok() {
+                            existing.push(parent.to_path_buf());
+                            break;
+                        }
+                        gone = parent;
+                    }
+                    removed.push(gone.to_path_buf());
+                }
+                _ => existing.push(path),
+            }
+        }
+        existing.sort_unstable();
+        existing.dedup();
+        Self {
+            paths: existing.into_iter(),
+            removed,
+            save_opts,
+            #[cfg(not(windows))]
+            cache: UsersCache::new(),
+        }
+    }
+
+    /// The given paths which don't exist
+    pub fn removed(&self) -> &[PathBuf] {
+        &self.removed
+    }
+}
+
+impl ReadSource for FilesSource {
+    type Open = OpenFile;
+    type Iter = Self;
+
+    fn size(&self) -> Result<Option<u64>> {
+        let mut size = 0;
+        for path in self.paths.as_slice() {
+            match symlink_metadata(path) {
+                Ok(m) => size += if m.is_dir() { 0 } else { m.len() },
+                Err(e) => warn!("ignoring error {}", e),
+            }
+        }
+        Ok(Some(size))
+    }
+
+    fn entries(self) -> Self::Iter {
+        self
+    }
+}
+
+impl Iterator for FilesSource {
+    type Item = Result<ReadSourceEntry<OpenFile>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let path = self.paths.next()?;
+        Some(
+            symlink_metadata(&path)
+                .with_context(|| format!("Unable to read {}", path.display()))
+                .and_then(|m| {
+                    map_entry(
+                        path,
+                        m,
+                        self.save_opts.with_atime,
+                        self.save_opts.ignore_devid,
+                        #[cfg(not(windows))]
+                        &self.cache,
+                    )
+                }),
+        )
+    }
+}
+
 #[cfg(windows)]
 fn map_entry(
-    entry: DirEntry,
+    path: PathBuf,
+    m: std::fs::Metadata,
     with_atime: bool,
     _ignore_devid: bool,
 ) -> Result<ReadSourceEntry<OpenFile>> {
-    let name = entry.file_name();
-    let m = entry.metadata()?;
+    let name = path.file_name().unwrap_or(path.as_os_str());
 
     // TODO: Set them to suitable values
     let uid = None;
@@ -274,7 +379,7 @@ fn map_entry(
     let node = if m.is_dir() {
         Node::new_node(name, NodeType::Dir, meta)
     } else if m.is_symlink() {
-        let target = read_link(entry.path())?;
+        let target = read_link(&path)?;
         let node_type = NodeType::Symlink {
             linktarget: target.to_str().expect("no unicode").to_string(),
         };
@@ -283,7 +388,6 @@ fn map_entry(
         Node::new_node(name, NodeType::File, meta)
     };
 
-    let path = entry.into_path();
     let open = Some(OpenFile(path.clone()));
     Ok(ReadSourceEntry { path, node, open })
 }
@@ -291,13 +395,13 @@ fn map_entry(
 #[cfg(not(windows))]
 // map_entry: turn entry into (Path, Node)
 fn map_entry(
-    entry: DirEntry,
+    path: PathBuf,
+    m: std::fs::Metadata,
     with_atime: bool,
     ignore_devid: bool,
     cache: &UsersCache,
 ) -> Result<ReadSourceEntry<OpenFile>> {
-    let name = entry.file_name();
-    let m = entry.metadata()?;
+    let name = path.file_name().unwrap_or(path.as_os_str());
 
     let uid = m.uid();
     let gid = m.gid();
@@ -337,12 +441,11 @@ fn map_entry(
 
     #[cfg(not(target_os = "openbsd"))]
     let extended_attributes = {
-        let path = entry.path();
-        xattr::list(path)?
+        xattr::list(&path)?
             .map(|name| {
                 Ok(ExtendedAttribute {
                     name: name.to_string_lossy().to_string(),
-                    value: xattr::get(path, name)?.unwrap(),
+                    value: xattr::get(&path, name)?.unwrap(),
                 })
             })
             .collect::<Result<_>>()?
@@ -368,7 +471,7 @@ fn map_entry(
     let node = if m.is_dir() {
         Node::new_node(name, NodeType::Dir, meta)
     } else if m.is_symlink() {
-        let target = read_link(entry.path())?;
+        let target = read_link(&path)?;
         let node_type = NodeType::Symlink {
             linktarget: target.to_str().expect("no unicode").to_string(),
         };
@@ -386,7 +489,6 @@ fn map_entry(
     } else {
         Node::new_node(name, NodeType::File, meta)
     };
-    let path = entry.into_path();
     let open = Some(OpenFile(path.clone()));
     Ok(ReadSourceEntry { path, node, open })
 }
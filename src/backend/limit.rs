@@ -0,0 +1,156 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use bytesize::ByteSize;
+
+use super::{FileType, Id, ReadBackend, WriteBackend};
+
+/// [`TokenBucket`] limits the throughput to `rate` bytes per second, allowing bursts of one second.
+/// Transfers larger than the bucket are allowed, but let the bucket run into debt, so that all
+/// following transfers have to wait. This way, concurrent transfers are accounted correctly.
+#[derive(Default)]
+struct TokenBucket {
+    state: Mutex<TokenState>,
+}
+
+#[derive(Default)]
+struct TokenState {
+    rate: Option<f64>,
+    tokens: f64,
+    last: Option<Instant>,
+}
+
+impl TokenBucket {
+    fn set_rate(&self, rate: Option<ByteSize>) {
+        let mut state = self.state.lock().unwrap();
+        state.rate = rate.map(|rate| rate.as_u64() as f64);
+        state.tokens = state.rate.unwrap_or_default();
+        state.last = None;
+    }
+
+    /// Take `size` bytes from the bucket and wait until the transfer is allowed
+    fn take(&self, size: usize) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let rate = match state.rate {
+                Some(rate) => rate,
+                None => return,
+            };
+            let now = Instant::now();
+            if let Some(last) = state.last {
+                let elapsed = now.duration_since(last).as_secs_f64();
+                state.tokens = (state.tokens + elapsed * rate).min(rate);
+            }
+            state.last = Some(now);
+            state.tokens -= size as f64;
+            match state.tokens < 0.0 {
+                true => Duration::from_secs_f64(-state.tokens / rate),
+                false => Duration::ZERO,
+            }
+        };
+        sleep(wait);
+    }
+}
+
+/// [`Limiter`] holds the upload and download limits. It can be shared by several backends,
+/// e.g. for the hot and cold part of a repository.
+#[derive(Default)]
+pub struct Limiter {
+    upload: TokenBucket,
+    download: TokenBucket,
+}
+
+impl Limiter {
+    pub fn set_upload(&self, limit: Option<ByteSize>) {
+        self.upload.set_rate(limit);
+    }
+
+    pub fn set_download(&self, limit: Option<ByteSize>) {
+        self.download.set_rate(limit);
+    }
+}
+
+fn parse_limit(value: &str) -> Result<Option<ByteSize>> {
+    Ok(match value {
+        "" | "0" | "unlimited" => None,
+        value => Some(ByteSize::from_str(value).map_err(|err| anyhow!(err))?),
+    })
+}
+
+#[derive(Clone)]
+pub struct LimitedBackend<BE: WriteBackend> {
+    be: BE,
+    limiter: Arc<Limiter>,
+}
+
+impl<BE: WriteBackend> LimitedBackend<BE> {
+    pub fn new(be: BE, limiter: Arc<Limiter>) -> Self {
+        Self { be, limiter }
+    }
+}
+
+impl<BE: WriteBackend> ReadBackend for LimitedBackend<BE> {
+    fn location(&self) -> String {
+        self.be.location()
+    }
+
+    fn set_option(&mut self, option: &str, value: &str) -> Result<()> {
+        match option {
+            "limit-upload" => self.limiter.set_upload(parse_limit(value)?),
+            "limit-download" => self.limiter.set_download(parse_limit(value)?),
+            _ => self.be.set_option(option, value)?,
+        }
+        Ok(())
+    }
+
+    fn list_with_size(&self, tpe: FileType) -> Result<Vec<(Id, u32)>> {
+        self.be.list_with_size(tpe)
+    }
+
+    fn read_full(&self, tpe: FileType, id: &Id) -> Result<Bytes> {
+        let data = self.be.read_full(tpe, id)?;
+        // the size is only known after reading, so wait afterwards
+        self.limiter.download.take(data.len());
+        Ok(data)
+    }
+
+    fn read_partial(
+        &self,
+        tpe: FileType,
+        id: &Id,
+        cacheable: bool,
+        offset: u32,
+        length: u32,
+    ) -> Result<Bytes> {
+        self.limiter.download.take(length.try_into()?);
+        self.be.read_partial(tpe, id, cacheable, offset, length)
+    }
+
+    fn list_orphaned(&self) -> Result<Vec<PathBuf>> {
+        self.be.list_orphaned()
+    }
+
+    fn list_divergence(&self, tpe: FileType) -> Result<Vec<String>> {
+        self.be.list_divergence(tpe)
+    }
+}
+
+impl<BE: WriteBackend> WriteBackend for LimitedBackend<BE> {
+    fn create(&self) -> Result<()> {
+        self.be.create()
+    }
+
+    fn write_bytes(&self, tpe: FileType, id: &Id, cacheable: bool, buf: Bytes) -> Result<()> {
+        self.limiter.upload.take(buf.len());
+        self.be.write_bytes(tpe, id, cacheable, buf)
+    }
+
+    fn remove(&self, tpe: FileType, id: &Id, cacheable: bool) -> Result<()> {
+        self.be.remove(tpe, id, cacheable)
+    }
+}
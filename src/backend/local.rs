@@ -11,11 +11,14 @@ use anyhow::Context;
 use anyhow::{anyhow, bail, Result};
 use bytes::Bytes;
 use filetime::{set_symlink_file_times, FileTime};
+#[cfg(not(windows))]
+use lazy_static::lazy_static;
 use log::*;
 #[cfg(not(windows))]
-use nix::sys::stat::{mknod, Mode, SFlag};
+use nix::sys::stat::{mknod, umask, Mode, SFlag};
 #[cfg(not(windows))]
 use nix::unistd::{fchownat, FchownatFlags, Gid, Group, Uid, User};
+use tempfile::Builder;
 use walkdir::WalkDir;
 
 use crate::repository::parse_command;
@@ -27,6 +30,9 @@ use super::node::NodeType;
 use super::node::{ExtendedAttribute, Metadata, Node};
 use super::{FileType, Id, ReadBackend, WriteBackend, ALL_FILE_TYPES};
 
+/// Prefix of temporary files which are renamed to their final name once completely written
+const TEMP_PREFIX: &str = ".rustic-tmp-";
+
 #[derive(Clone)]
 pub struct LocalBackend {
     path: PathBuf,
@@ -34,8 +40,26 @@ pub struct LocalBackend {
     post_delete_command: Option<String>,
 }
 
+#[cfg(not(windows))]
+lazy_static! {
+    // umask of the process; it can only be read by setting it, so this is done once
+    static ref UMASK: u32 = read_umask();
+}
+
+#[cfg(not(windows))]
+// mode_t is not u32 on all platforms (e.g. on macOS), so the conversion is needed there
+#[allow(clippy::useless_conversion)]
+fn read_umask() -> u32 {
+    let mask = umask(Mode::empty());
+    let _ = umask(mask);
+    u32::from(mask.bits())
+}
+
 impl LocalBackend {
     pub fn new(path: &str) -> Result<Self> {
+        // read the umask before any files are written in parallel
+        #[cfg(not(windows))]
+        lazy_static::initialize(&UMASK);
         let path = path.into();
         fs::create_dir_all(&path)?;
         Ok(Self {
@@ -146,6 +170,18 @@ impl ReadBackend for LocalBackend {
         Ok(fs::read(self.path(tpe, id))?.into())
     }
 
+    fn list_orphaned(&self) -> Result<Vec<PathBuf>> {
+        Ok(WalkDir::new(&self.path)
+            .max_depth(3)
+            .into_iter()
+            .filter_map(walkdir::Result::ok)
+            .filter(|e| {
+                e.file_type().is_file() && e.file_name().to_string_lossy().starts_with(TEMP_PREFIX)
+            })
+            .map(walkdir::DirEntry::into_path)
+            .collect())
+    }
+
     fn read_partial(
         &self,
         tpe: FileType,
@@ -179,13 +215,26 @@ impl WriteBackend for LocalBackend {
     fn write_bytes(&self, tpe: FileType, id: &Id, _cacheable: bool, buf: Bytes) -> Result<()> {
         trace!("writing tpe: {:?}, id: {}", &tpe, &id);
         let filename = self.path(tpe, id);
-        let mut file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(&filename)?;
-        file.set_len(buf.len().try_into()?)?;
+        let dir = filename
+            .parent()
+            .ok_or_else(|| anyhow!("file {filename:?} should have a parent"))?;
+
+        // write to a temporary file and rename it when completed, so that an interrupted
+        // write never leaves a truncated file under the final name.
+        // If writing fails, the temporary file is removed on drop.
+        let mut file = Builder::new().prefix(TEMP_PREFIX).tempfile_in(dir)?;
+        // use the same permissions as File::create, i.e. 0o666 restricted by the umask
+        // instead of 0o600 which tempfile uses
+        #[cfg(not(windows))]
+        file.as_file()
+            .set_permissions(fs::Permissions::from_mode(0o666 & !*UMASK))?;
         file.write_all(&buf)?;
-        file.sync_all()?;
+        file.as_file().sync_all()?;
+        let _ = file.persist(&filename)?;
+        // sync the directory to make the rename durable
+        #[cfg(not(windows))]
+        File::open(dir)?.sync_all()?;
+
         if let Some(command) = &self.post_create_command {
             if let Err(err) = self.call_command(tpe, id, &filename, command) {
                 warn!("post-create: {err}");
@@ -400,6 +449,26 @@ impl LocalDestination {
         Ok(())
     }
 
+    // create_hardlink creates link as hard link to original. An existing file at link is replaced.
+    pub fn create_hardlink(
+        &self,
+        original: impl AsRef<Path>,
+        link: impl AsRef<Path>,
+    ) -> Result<()> {
+        let original = self.path(original);
+        let link = self.path(link);
+        let dir = link
+            .parent()
+            .ok_or_else(|| anyhow!("file {link:?} should have a parent"))?;
+        fs::create_dir_all(dir)?;
+
+        if link.symlink_metadata().is_ok() {
+            fs::remove_file(&link)?;
+        }
+        fs::hard_link(original, link)?;
+        Ok(())
+    }
+
     #[cfg(windows)]
     // TODO
     pub fn create_special(&self, _item: impl AsRef<Path>, _node: &Node) -> Result<()> {
@@ -485,3 +554,49 @@ impl LocalDestination {
         Ok(())
     }
 }
+
+#[cfg(not(windows))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::fs::MetadataExt;
+
+    #[test]
+    fn restore_hardlink() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dest = LocalDestination::new(dir.path().to_str().unwrap(), true, false)?;
+        dest.write_at("file", 0, b"content")?;
+        // an existing file is replaced by the link
+        dest.write_at("other", 0, b"other")?;
+        dest.create_hardlink("file", "other")?;
+        dest.create_hardlink("file", "dir/link")?;
+
+        let ino = fs::metadata(dir.path().join("file"))?.ino();
+        for link in ["other", "dir/link"] {
+            let path = dir.path().join(link);
+            assert_eq!(fs::metadata(&path)?.ino(), ino);
+            assert_eq!(fs::read(&path)?, b"content");
+        }
+        assert_eq!(fs::metadata(dir.path().join("file"))?.nlink(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn write_bytes_permissions() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let be = LocalBackend::new(dir.path().to_str().unwrap())?;
+        be.write_bytes(
+            FileType::Config,
+            &Id::default(),
+            false,
+            Bytes::from("config"),
+        )?;
+
+        let created = dir.path().join("created");
+        drop(File::create(&created)?);
+        let mode = |path: PathBuf| -> Result<u32> { Ok(fs::metadata(path)?.permissions().mode()) };
+        assert_eq!(mode(dir.path().join("config"))?, mode(created)?);
+        Ok(())
+    }
+}
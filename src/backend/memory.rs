@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+use lazy_static::lazy_static;
+use log::*;
+
+use super::{FileType, Id, ReadBackend, WriteBackend};
+
+type Files = Arc<RwLock<HashMap<(FileType, Id), Bytes>>>;
+
+lazy_static! {
+    // named memory backends share their files within the process
+    static ref NAMED_FILES: Mutex<HashMap<String, Files>> = Mutex::new(HashMap::new());
+}
+
+/// Faults to inject into a [`MemoryBackend`]. Counts start at 1; 0 means no fault.
+#[derive(Default)]
+struct Faults {
+    fail_write: AtomicUsize,
+    corrupt_read: AtomicUsize,
+    truncate_list: AtomicUsize,
+    writes: AtomicUsize,
+    reads: AtomicUsize,
+}
+
+impl Faults {
+    /// Count the access and check whether it is the one selected by `fault`
+    fn hit(counter: &AtomicUsize, fault: &AtomicUsize) -> bool {
+        let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+        n == fault.load(Ordering::SeqCst)
+    }
+}
+
+/// [`MemoryBackend`] keeps all files in memory. It is meant for tests and ephemeral repositories.
+///
+/// `memory:` always starts with an empty repository while all backends opened with
+/// `memory:NAME` share their files within the running process.
+#[derive(Clone)]
+pub struct MemoryBackend {
+    name: String,
+    files: Files,
+    faults: Arc<Faults>,
+}
+
+impl MemoryBackend {
+    pub fn new(name: &str) -> Self {
+        let files = match name.is_empty() {
+            true => Files::default(),
+            false => NAMED_FILES
+                .lock()
+                .unwrap()
+                .entry(name.to_string())
+                .or_default()
+                .clone(),
+        };
+        Self {
+            name: name.to_string(),
+            files,
+            faults: Arc::default(),
+        }
+    }
+
+    fn get(&self, tpe: FileType, id: &Id) -> Result<Bytes> {
+        self.files
+            .read()
+            .unwrap()
+            .get(&(tpe, *id))
+            .cloned()
+            .ok_or_else(|| anyhow!("file {tpe:?}/{id} does not exist"))
+    }
+
+    /// Corrupt the returned data if this read is selected by the corrupt-read fault
+    fn corrupt(&self, data: Bytes) -> Bytes {
+        if Faults::hit(&self.faults.reads, &self.faults.corrupt_read) && !data.is_empty() {
+            let mut data = data.to_vec();
+            data[0] ^= 0xff;
+            return data.into();
+        }
+        data
+    }
+}
+
+impl ReadBackend for MemoryBackend {
+    fn location(&self) -> String {
+        let mut location = "memory:".to_string();
+        location.push_str(&self.name);
+        location
+    }
+
+    fn set_option(&mut self, option: &str, value: &str) -> Result<()> {
+        let fault = match option {
+            "fail-write" => &self.faults.fail_write,
+            "corrupt-read" => &self.faults.corrupt_read,
+            "truncate-list" => &self.faults.truncate_list,
+            opt => {
+                warn!("Option {opt} is not supported! Ignoring it.");
+                return Ok(());
+            }
+        };
+        fault.store(value.parse()?, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn list_with_size(&self, tpe: FileType) -> Result<Vec<(Id, u32)>> {
+        trace!("listing tpe: {tpe:?}");
+        let mut list = self
+            .files
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|((t, _), _)| *t == tpe)
+            .map(|((_, id), data)| Ok((*id, data.len().try_into()?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let truncate = self.faults.truncate_list.load(Ordering::SeqCst);
+        if truncate > 0 {
+            list.truncate(truncate);
+        }
+        Ok(list)
+    }
+
+    fn read_full(&self, tpe: FileType, id: &Id) -> Result<Bytes> {
+        trace!("reading tpe: {tpe:?}, id: {id}");
+        Ok(self.corrupt(self.get(tpe, id)?))
+    }
+
+    fn read_partial(
+        &self,
+        tpe: FileType,
+        id: &Id,
+        _cacheable: bool,
+        offset: u32,
+        length: u32,
+    ) -> Result<Bytes> {
+        trace!("reading tpe: {tpe:?}, id: {id}, offset: {offset}, length: {length}");
+        let data = self.get(tpe, id)?;
+        let end = offset
+            .checked_add(length)
+            .ok_or_else(|| anyhow!("offset {offset} + length {length} is too large"))?;
+        let (start, end): (usize, usize) = (offset.try_into()?, end.try_into()?);
+        if end > data.len() {
+            bail!("file {tpe:?}/{id} is shorter than {end} bytes");
+        }
+        Ok(self.corrupt(data.slice(start..end)))
+    }
+}
+
+impl WriteBackend for MemoryBackend {
+    fn create(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_bytes(&self, tpe: FileType, id: &Id, _cacheable: bool, buf: Bytes) -> Result<()> {
+        trace!("writing tpe: {:?}, id: {}", &tpe, &id);
+        if Faults::hit(&self.faults.writes, &self.faults.fail_write) {
+            bail!("injected failure writing {tpe:?}/{id}");
+        }
+        self.files.write().unwrap().insert((tpe, *id), buf);
+        Ok(())
+    }
+
+    fn remove(&self, tpe: FileType, id: &Id, _cacheable: bool) -> Result<()> {
+        trace!("removing tpe: {:?}, id: {}", &tpe, &id);
+        match self.files.write().unwrap().remove(&(tpe, *id)) {
+            Some(_) => Ok(()),
+            None => bail!("file {tpe:?}/{id} does not exist"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(be: &MemoryBackend, data: &'static [u8]) -> Result<Id> {
+        let id = Id::random();
+        be.write_bytes(FileType::Pack, &id, false, Bytes::from_static(data))?;
+        Ok(id)
+    }
+
+    #[test]
+    fn named_backends_share_files() -> Result<()> {
+        let be1 = MemoryBackend::new("shared");
+        let id = write(&be1, b"test")?;
+        let be2 = MemoryBackend::new("shared");
+        assert_eq!(
+            be2.read_partial(FileType::Pack, &id, false, 1, 2)?,
+            &b"es"[..]
+        );
+        assert!(MemoryBackend::new("").list(FileType::Pack)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn fail_nth_write() -> Result<()> {
+        let mut be = MemoryBackend::new("");
+        be.set_option("fail-write", "2")?;
+        assert!(write(&be, b"1").is_ok());
+        assert!(write(&be, b"2").is_err());
+        assert!(write(&be, b"3").is_ok());
+        assert_eq!(be.list(FileType::Pack)?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_read_and_truncate_list() -> Result<()> {
+        let mut be = MemoryBackend::new("");
+        be.set_option("corrupt-read", "1")?;
+        be.set_option("truncate-list", "1")?;
+        let id = write(&be, b"data")?;
+        let _ = write(&be, b"more data")?;
+        assert_ne!(be.read_full(FileType::Pack, &id)?, &b"data"[..]);
+        assert_eq!(be.read_full(FileType::Pack, &id)?, &b"data"[..]);
+        assert_eq!(be.list(FileType::Pack)?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_partial_read() -> Result<()> {
+        let mut be = MemoryBackend::new("");
+        be.set_option("corrupt-read", "1")?;
+        let id = write(&be, b"data")?;
+        assert_ne!(
+            be.read_partial(FileType::Pack, &id, false, 2, 2)?,
+            &b"ta"[..]
+        );
+        assert_eq!(
+            be.read_partial(FileType::Pack, &id, false, 2, 2)?,
+            &b"ta"[..]
+        );
+        assert!(be
+            .read_partial(FileType::Pack, &id, false, u32::MAX, 2)
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use log::*;
+
+use super::{FileType, Id, ReadBackend, WriteBackend};
+
+/// [`MirrorBackend`] writes to all of its members and reads from the first member which
+/// successfully answers the request.
+///
+/// Writes and removes are successful if at least `quorum` members succeeded.
+#[derive(Clone)]
+pub struct MirrorBackend<BE: WriteBackend> {
+    members: Vec<BE>,
+    quorum: usize,
+}
+
+impl<BE: WriteBackend> MirrorBackend<BE> {
+    /// Create a new mirror. By default, all members must succeed.
+    pub fn new(members: Vec<BE>) -> Result<Self> {
+        if members.is_empty() {
+            bail!("mirror needs at least one member");
+        }
+        let quorum = members.len();
+        Ok(Self { members, quorum })
+    }
+
+    /// Read from the first member which is able to answer
+    fn read(&self, f: impl Fn(&BE) -> Result<Bytes>) -> Result<Bytes> {
+        let mut last_err = None;
+        for be in &self.members {
+            match f(be) {
+                Ok(data) => return Ok(data),
+                Err(err) => {
+                    warn!("mirror member {}: {err}, trying next member", be.location());
+                    last_err = Some(err);
+                }
+            }
+        }
+        Err(last_err.unwrap())
+    }
+
+    /// Run `f` on all members and check that the quorum is reached
+    fn write(&self, action: &str, f: impl Fn(&BE) -> Result<()>) -> Result<()> {
+        let mut success = 0;
+        for be in &self.members {
+            match f(be) {
+                Ok(()) => success += 1,
+                Err(err) => warn!("mirror member {}: {action} failed: {err}", be.location()),
+            }
+        }
+        if success < self.quorum {
+            bail!(
+                "{action} succeeded only for {success} of {} mirror members, quorum is {}",
+                self.members.len(),
+                self.quorum
+            );
+        }
+        Ok(())
+    }
+}
+
+impl<BE: WriteBackend> ReadBackend for MirrorBackend<BE> {
+    fn location(&self) -> String {
+        let locations: Vec<_> = self.members.iter().map(ReadBackend::location).collect();
+        let mut location = "mirror:".to_string();
+        location.push_str(&locations.join("|"));
+        location
+    }
+
+    fn set_option(&mut self, option: &str, value: &str) -> Result<()> {
+        if option == "quorum" {
+            let quorum = value.parse()?;
+            if quorum == 0 || quorum > self.members.len() {
+                bail!("quorum must be between 1 and {}", self.members.len());
+            }
+            self.quorum = quorum;
+            return Ok(());
+        }
+        for be in &mut self.members {
+            be.set_option(option, value)?;
+        }
+        Ok(())
+    }
+
+    fn list_with_size(&self, tpe: FileType) -> Result<Vec<(Id, u32)>> {
+        let mut last_err = None;
+        for be in &self.members {
+            match be.list_with_size(tpe) {
+                Ok(list) => return Ok(list),
+                Err(err) => {
+                    warn!("mirror member {}: {err}, trying next member", be.location());
+                    last_err = Some(err);
+                }
+            }
+        }
+        Err(last_err.unwrap())
+    }
+
+    fn read_full(&self, tpe: FileType, id: &Id) -> Result<Bytes> {
+        self.read(|be| be.read_full(tpe, id))
+    }
+
+    fn read_partial(
+        &self,
+        tpe: FileType,
+        id: &Id,
+        cacheable: bool,
+        offset: u32,
+        length: u32,
+    ) -> Result<Bytes> {
+        self.read(|be| be.read_partial(tpe, id, cacheable, offset, length))
+    }
+
+    fn list_orphaned(&self) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for be in &self.members {
+            files.extend(be.list_orphaned()?);
+        }
+        Ok(files)
+    }
+
+    fn list_divergence(&self, tpe: FileType) -> Result<Vec<String>> {
+        // maps id to the sizes of the file in each member
+        let mut files: BTreeMap<Id, Vec<Option<u32>>> = BTreeMap::new();
+        for (i, be) in self.members.iter().enumerate() {
+            for (id, size) in be.list_with_size(tpe)? {
+                files
+                    .entry(id)
+                    .or_insert_with(|| vec![None; self.members.len()])[i] = Some(size);
+            }
+        }
+
+        let mut divergence = Vec::new();
+        for (id, sizes) in files {
+            let expected = sizes.iter().flatten().max().copied();
+            for (be, size) in self.members.iter().zip(sizes) {
+                match size {
+                    None => divergence.push(format!(
+                        "{tpe:?} {id} is missing in mirror member {}",
+                        be.location()
+                    )),
+                    // some backends don't report the size of the config file
+                    Some(size) if tpe != FileType::Config && Some(size) != expected => divergence
+                        .push(format!(
+                            "{tpe:?} {id} has size {size} in mirror member {}, expected {}",
+                            be.location(),
+                            expected.unwrap_or_default()
+                        )),
+                    Some(_) => {}
+                }
+            }
+        }
+        Ok(divergence)
+    }
+}
+
+impl<BE: WriteBackend> WriteBackend for MirrorBackend<BE> {
+    fn create(&self) -> Result<()> {
+        self.write("create", WriteBackend::create)
+    }
+
+    fn write_bytes(&self, tpe: FileType, id: &Id, cacheable: bool, buf: Bytes) -> Result<()> {
+        self.write(&format!("writing {tpe:?} {id}"), |be| {
+            be.write_bytes(tpe, id, cacheable, buf.clone())
+        })
+    }
+
+    fn remove(&self, tpe: FileType, id: &Id, cacheable: bool) -> Result<()> {
+        self.write(&format!("removing {tpe:?} {id}"), |be| {
+            be.remove(tpe, id, cacheable)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::MemoryBackend;
+    use super::*;
+
+    fn mirror() -> Result<MirrorBackend<MemoryBackend>> {
+        MirrorBackend::new(vec![MemoryBackend::new(""), MemoryBackend::new("")])
+    }
+
+    #[test]
+    fn write_to_all_read_from_healthy() -> Result<()> {
+        let mut be = mirror()?;
+        let id = Id::random();
+        be.write_bytes(FileType::Pack, &id, false, Bytes::from_static(b"data"))?;
+        for member in &be.members {
+            assert_eq!(member.read_full(FileType::Pack, &id)?, &b"data"[..]);
+        }
+
+        be.members[0].remove(FileType::Pack, &id, false)?;
+        assert_eq!(be.read_full(FileType::Pack, &id)?, &b"data"[..]);
+        assert_eq!(be.list_divergence(FileType::Pack)?.len(), 1);
+
+        be.set_option("quorum", "1")?;
+        be.remove(FileType::Pack, &id, false)?;
+        assert!(be.list_divergence(FileType::Pack)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn quorum() -> Result<()> {
+        let mut be = mirror()?;
+        be.members[1].set_option("fail-write", "1")?;
+        let write = |be: &MirrorBackend<MemoryBackend>| {
+            be.write_bytes(FileType::Pack, &Id::random(), false, Bytes::new())
+        };
+        assert!(write(&be).is_err());
+        assert!(write(&be).is_ok());
+
+        be.set_option("quorum", "1")?;
+        be.members[1].set_option("fail-write", "3")?;
+        assert!(write(&be).is_ok());
+        assert!(be.set_option("quorum", "3").is_err());
+        Ok(())
+    }
+}
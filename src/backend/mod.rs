@@ -13,22 +13,36 @@ pub mod decrypt;
 pub mod dry_run;
 pub mod hotcold;
 pub mod ignore;
+pub mod limit;
 pub mod local;
+pub mod memory;
+pub mod mirror;
 pub mod node;
 pub mod rclone;
 pub mod rest;
+pub mod retry;
+pub mod s3;
+pub mod sftp;
 pub mod stdin;
+pub mod tar;
 
 pub use self::ignore::*;
+pub use self::tar::*;
 pub use cache::*;
 pub use choose::*;
 pub use decrypt::*;
 pub use dry_run::*;
 pub use hotcold::*;
+pub use limit::*;
 pub use local::*;
+pub use memory::*;
+pub use mirror::*;
 use node::Node;
 pub use rclone::*;
 pub use rest::*;
+pub use retry::*;
+pub use s3::*;
+pub use sftp::*;
 pub use stdin::*;
 
 /// All [`FileType`]s which are located in separated directories
@@ -39,7 +53,7 @@ pub const ALL_FILE_TYPES: [FileType; 4] = [
     FileType::Pack,
 ];
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum FileType {
     Config,
     Index,
@@ -96,6 +110,16 @@ pub trait ReadBackend: Clone + Send + Sync + 'static {
         length: u32,
     ) -> Result<Bytes>;
 
+    /// List files which are left over from interrupted writes, e.g. temporary files
+    fn list_orphaned(&self) -> Result<Vec<PathBuf>> {
+        Ok(Vec::new())
+    }
+
+    /// List differences between the members of a mirrored backend
+    fn list_divergence(&self, _tpe: FileType) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
     fn find_starts_with(&self, tpe: FileType, vec: &[String]) -> Result<Vec<Id>> {
         #[derive(Clone, Copy, PartialEq, Eq)]
         pub enum MapResult<T> {
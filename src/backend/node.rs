@@ -117,6 +117,14 @@ impl Node {
     pub fn name(&self) -> OsString {
         unescape_filename(&self.name).unwrap_or_else(|_| OsString::from_str(&self.name).unwrap())
     }
+
+    /// Identifies files with several hard links by device id and inode.
+    /// Returns `None` if the file has a single link or the ids are unknown, e.g. with --ignore-devid.
+    pub fn hardlink_key(&self) -> Option<(u64, u64)> {
+        let meta = &self.meta;
+        (meta.links > 1 && meta.inode != 0 && meta.device_id != 0)
+            .then_some((meta.device_id, meta.inode))
+    }
 }
 
 // TODO(Windows): This is not able to handle non-unicode filenames and
@@ -272,6 +280,27 @@ mod tests {
         }
     }
 
+    #[rstest]
+    #[case(2, 5, 3, Some((3, 5)))]
+    #[case(1, 5, 3, None)]
+    #[case(2, 0, 3, None)]
+    #[case(2, 5, 0, None)]
+    fn hardlink_key(
+        #[case] links: u64,
+        #[case] inode: u64,
+        #[case] device_id: u64,
+        #[case] expected: Option<(u64, u64)>,
+    ) {
+        let meta = Metadata {
+            links,
+            inode,
+            device_id,
+            ..Default::default()
+        };
+        let node = Node::new_node(OsStr::new("file"), NodeType::File, meta);
+        assert_eq!(node.hardlink_key(), expected);
+    }
+
     #[rstest]
     #[case(b"\\", r#"\\"#)]
     #[case(b"\"", r#"\""#)]
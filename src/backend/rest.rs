@@ -1,61 +1,137 @@
+use std::error::Error as StdError;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::Duration;
 
-use anyhow::{bail, Result};
-use backoff::{backoff::Backoff, Error, ExponentialBackoff, ExponentialBackoffBuilder};
+use anyhow::{bail, Context, Result};
+use backoff::Error;
 use bytes::Bytes;
 use log::*;
-use reqwest::blocking::{Client, ClientBuilder, Response};
-use reqwest::header::{HeaderMap, HeaderValue};
-use reqwest::Url;
+use reqwest::blocking::{Client, ClientBuilder, RequestBuilder, Response};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Certificate, Identity, Url};
 use serde::Deserialize;
 
+use super::retry::{parse_retry_after, RetryPolicy};
 use super::{FileType, Id, ReadBackend, WriteBackend};
 
 // trait CheckError to add user-defined methoed check_error on Response
 trait CheckError {
-    fn check_error(self) -> std::result::Result<Response, Error<reqwest::Error>>;
+    fn check_error(
+        self,
+        policy: &RetryPolicy,
+    ) -> std::result::Result<Response, Error<reqwest::Error>>;
 }
 
 impl CheckError for Response {
     // Check reqwest Response for error and treat errors as permanent or transient
-    fn check_error(self) -> std::result::Result<Response, Error<reqwest::Error>> {
+    fn check_error(
+        self,
+        policy: &RetryPolicy,
+    ) -> std::result::Result<Response, Error<reqwest::Error>> {
+        let retry_after = self
+            .headers()
+            .get("Retry-After")
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
         match self.error_for_status() {
             Ok(t) => Ok(t),
             // Note: status() always give Some(_) as it is called from a Response
-            Err(err) if err.status().unwrap().is_client_error() => Err(Error::Permanent(err)),
-            Err(err) => Err(Error::Transient {
+            Err(err) if policy.is_transient(err.status().unwrap().as_u16()) => {
+                Err(Error::Transient { err, retry_after })
+            }
+            Err(err) => Err(Error::Permanent(err)),
+        }
+    }
+}
+
+// trait SendRequest to add user-defined method send_request on RequestBuilder
+trait SendRequest {
+    fn send_request(self) -> std::result::Result<Response, Error<reqwest::Error>>;
+}
+
+impl SendRequest for RequestBuilder {
+    // Send the request and treat errors due to certificate problems as permanent,
+    // as retrying won't help in this case
+    fn send_request(self) -> std::result::Result<Response, Error<reqwest::Error>> {
+        self.send().map_err(|err| match certificate_error(&err) {
+            Some(cause) => {
+                error!("certificate problem: {cause}");
+                Error::Permanent(err)
+            }
+            None => Error::Transient {
                 err,
                 retry_after: None,
-            }),
+            },
+        })
+    }
+}
+
+// Search the causes of a connection error for a problem with a TLS certificate
+fn certificate_error(err: &reqwest::Error) -> Option<String> {
+    if !err.is_connect() {
+        return None;
+    }
+    let mut source = err.source();
+    while let Some(err) = source {
+        let cause = err.to_string();
+        if cause.to_lowercase().contains("certificate") {
+            return Some(cause);
         }
+        source = err.source();
     }
+    None
 }
 
-#[derive(Clone)]
-struct MaybeBackoff(Option<ExponentialBackoff>);
+/// Settings used to build the HTTP client
+#[derive(Clone, Default)]
+struct ClientSettings {
+    timeout: Option<Duration>,
+    cacert: Option<PathBuf>,
+    tls_client_cert: Option<PathBuf>,
+    tls_client_key: Option<PathBuf>,
+    headers: HeaderMap,
+}
 
-impl Backoff for MaybeBackoff {
-    fn next_backoff(&mut self) -> Option<Duration> {
-        self.0.as_mut().and_then(|back| back.next_backoff())
-    }
+impl ClientSettings {
+    fn client(&self) -> Result<Client> {
+        let mut headers = HeaderMap::new();
+        headers.insert("User-Agent", HeaderValue::from_static("rustic"));
+        headers.extend(self.headers.clone());
 
-    fn reset(&mut self) {
-        if let Some(b) = self.0.as_mut() {
-            b.reset();
+        let mut builder = ClientBuilder::new().default_headers(headers);
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(file) = &self.cacert {
+            let pem =
+                std::fs::read(file).with_context(|| format!("error reading cacert {file:?}"))?;
+            let cert = Certificate::from_pem(&pem)
+                .with_context(|| format!("error parsing cacert {file:?}"))?;
+            builder = builder.add_root_certificate(cert);
         }
+        if let (Some(cert), Some(key)) = (&self.tls_client_cert, &self.tls_client_key) {
+            let mut pem = std::fs::read(cert)
+                .with_context(|| format!("error reading tls-client-cert {cert:?}"))?;
+            pem.push(b'\n');
+            pem.extend(
+                std::fs::read(key)
+                    .with_context(|| format!("error reading tls-client-key {key:?}"))?,
+            );
+            let identity = Identity::from_pem(&pem)
+                .with_context(|| format!("error parsing client certificate {cert:?}"))?;
+            builder = builder.identity(identity);
+        }
+        Ok(builder.build()?)
     }
 }
 
 #[derive(Clone)]
 pub struct RestBackend {
     url: Url,
+    settings: ClientSettings,
     client: Client,
-    backoff: MaybeBackoff,
-}
-
-fn notify(err: reqwest::Error, duration: Duration) {
-    warn!("Error {err} at {duration:?}, retrying");
+    retry_policy: RetryPolicy,
 }
 
 impl RestBackend {
@@ -69,22 +145,30 @@ impl RestBackend {
             Url::parse(&url)?
         };
 
-        let mut headers = HeaderMap::new();
-        headers.insert("User-Agent", HeaderValue::from_static("rustic"));
-
-        let client = ClientBuilder::new().default_headers(headers).build()?;
+        let settings = ClientSettings::default();
 
         Ok(Self {
             url,
-            client,
-            backoff: MaybeBackoff(Some(
-                ExponentialBackoffBuilder::new()
-                    .with_max_elapsed_time(Some(Duration::from_secs(600)))
-                    .build(),
-            )),
+            client: settings.client()?,
+            settings,
+            retry_policy: RetryPolicy::default(),
         })
     }
 
+    fn retry<T>(
+        &self,
+        op: impl FnMut() -> std::result::Result<T, Error<reqwest::Error>>,
+    ) -> Result<T> {
+        self.retry_policy.retry(op)
+    }
+
+    fn client(&self) -> Result<&Client> {
+        if self.settings.tls_client_cert.is_some() != self.settings.tls_client_key.is_some() {
+            bail!("tls-client-cert and tls-client-key must be given together!");
+        }
+        Ok(&self.client)
+    }
+
     fn url(&self, tpe: FileType, id: &Id) -> Result<Url> {
         let id_path = match tpe {
             FileType::Config => "config".to_string(),
@@ -112,24 +196,32 @@ impl ReadBackend for RestBackend {
     }
 
     fn set_option(&mut self, option: &str, value: &str) -> Result<()> {
-        if option == "retry" {
-            match value {
-                "true" => {
-                    self.backoff = MaybeBackoff(Some(
-                        ExponentialBackoffBuilder::new()
-                            .with_max_elapsed_time(Some(Duration::from_secs(120)))
-                            .build(),
-                    ));
+        if self.retry_policy.set_option(option, value)? {
+            return Ok(());
+        }
+
+        match option {
+            "timeout" => self.settings.timeout = Some(*humantime::Duration::from_str(value)?),
+            "cacert" => self.settings.cacert = Some(value.into()),
+            "tls-client-cert" => self.settings.tls_client_cert = Some(value.into()),
+            "tls-client-key" => self.settings.tls_client_key = Some(value.into()),
+            opt => match opt.strip_prefix("header-") {
+                Some(name) => {
+                    let name = HeaderName::from_str(name)?;
+                    let _ = self
+                        .settings
+                        .headers
+                        .insert(name, HeaderValue::from_str(value)?);
                 }
-                "false" => {
-                    self.backoff = MaybeBackoff(None);
+                None => {
+                    warn!("Option {opt} is not supported! Ignoring it.");
+                    return Ok(());
                 }
-                val => bail!("value {val} not supported for option retry!"),
-            }
-        } else if option == "timeout" {
-            let timeout = humantime::Duration::from_str(value)?;
-            self.client = ClientBuilder::new().timeout(*timeout).build()?;
+            },
         }
+        // client cert and key are set by separate options, so an incomplete pair is only
+        // reported when connecting
+        self.client = self.settings.client()?;
         Ok(())
     }
 
@@ -143,59 +235,56 @@ impl ReadBackend for RestBackend {
             self.url.join(&path)?
         };
 
-        Ok(backoff::retry_notify(
-            self.backoff.clone(),
-            || {
-                if tpe == FileType::Config {
-                    return Ok(
-                        match self.client.head(url.clone()).send()?.status().is_success() {
-                            true => vec![(Id::default(), 0)],
-                            false => Vec::new(),
-                        },
-                    );
-                }
+        let client = self.client()?;
+        self.retry(|| {
+            if tpe == FileType::Config {
+                return Ok(
+                    match client
+                        .head(url.clone())
+                        .send_request()?
+                        .status()
+                        .is_success()
+                    {
+                        true => vec![(Id::default(), 0)],
+                        false => Vec::new(),
+                    },
+                );
+            }
 
-                // format which is delivered by the REST-service
-                #[derive(Deserialize)]
-                struct ListEntry {
-                    name: String,
-                    size: u32,
-                }
+            // format which is delivered by the REST-service
+            #[derive(Deserialize)]
+            struct ListEntry {
+                name: String,
+                size: u32,
+            }
 
-                let list = self
-                    .client
-                    .get(url.clone())
-                    .header("Accept", "application/vnd.x.restic.rest.v2")
-                    .send()?
-                    .check_error()?
-                    .json::<Vec<ListEntry>>()?;
-                Ok(list
-                    .into_iter()
-                    .filter_map(|i| match Id::from_hex(&i.name) {
-                        Ok(id) => Some((id, i.size)),
-                        Err(_) => None,
-                    })
-                    .collect())
-            },
-            notify,
-        )?)
+            let list = client
+                .get(url.clone())
+                .header("Accept", "application/vnd.x.restic.rest.v2")
+                .send_request()?
+                .check_error(&self.retry_policy)?
+                .json::<Vec<ListEntry>>()?;
+            Ok(list
+                .into_iter()
+                .filter_map(|i| match Id::from_hex(&i.name) {
+                    Ok(id) => Some((id, i.size)),
+                    Err(_) => None,
+                })
+                .collect())
+        })
     }
 
     fn read_full(&self, tpe: FileType, id: &Id) -> Result<Bytes> {
         trace!("reading tpe: {tpe:?}, id: {id}");
         let url = self.url(tpe, id)?;
-        Ok(backoff::retry_notify(
-            self.backoff.clone(),
-            || {
-                Ok(self
-                    .client
-                    .get(url.clone())
-                    .send()?
-                    .check_error()?
-                    .bytes()?)
-            },
-            notify,
-        )?)
+        let client = self.client()?;
+        self.retry(|| {
+            Ok(client
+                .get(url.clone())
+                .send_request()?
+                .check_error(&self.retry_policy)?
+                .bytes()?)
+        })
     }
 
     fn read_partial(
@@ -210,59 +299,55 @@ impl ReadBackend for RestBackend {
         let offset2 = offset + length - 1;
         let header_value = format!("bytes={offset}-{offset2}");
         let url = self.url(tpe, id)?;
-        Ok(backoff::retry_notify(
-            self.backoff.clone(),
-            || {
-                Ok(self
-                    .client
-                    .get(url.clone())
-                    .header("Range", header_value.clone())
-                    .send()?
-                    .check_error()?
-                    .bytes()?)
-            },
-            notify,
-        )?)
+        let client = self.client()?;
+        self.retry(|| {
+            Ok(client
+                .get(url.clone())
+                .header("Range", header_value.clone())
+                .send_request()?
+                .check_error(&self.retry_policy)?
+                .bytes()?)
+        })
     }
 }
 
 impl WriteBackend for RestBackend {
     fn create(&self) -> Result<()> {
         let url = self.url.join("?create=true")?;
-        Ok(backoff::retry_notify(
-            self.backoff.clone(),
-            || {
-                self.client.post(url.clone()).send()?.check_error()?;
-                Ok(())
-            },
-            notify,
-        )?)
+        let client = self.client()?;
+        self.retry(|| {
+            client
+                .post(url.clone())
+                .send_request()?
+                .check_error(&self.retry_policy)?;
+            Ok(())
+        })
     }
 
     fn write_bytes(&self, tpe: FileType, id: &Id, _cacheable: bool, buf: Bytes) -> Result<()> {
         trace!("writing tpe: {:?}, id: {}", &tpe, &id);
-        let req_builder = self.client.post(self.url(tpe, id)?).body(buf);
-        Ok(backoff::retry_notify(
-            self.backoff.clone(),
-            || {
-                // Note: try_clone() always gives Some(_) as the body is Bytes which is clonable
-                req_builder.try_clone().unwrap().send()?.check_error()?;
-                Ok(())
-            },
-            notify,
-        )?)
+        let req_builder = self.client()?.post(self.url(tpe, id)?).body(buf);
+        self.retry(|| {
+            // Note: try_clone() always gives Some(_) as the body is Bytes which is clonable
+            req_builder
+                .try_clone()
+                .unwrap()
+                .send_request()?
+                .check_error(&self.retry_policy)?;
+            Ok(())
+        })
     }
 
     fn remove(&self, tpe: FileType, id: &Id, _cacheable: bool) -> Result<()> {
         trace!("removing tpe: {:?}, id: {}", &tpe, &id);
         let url = self.url(tpe, id)?;
-        Ok(backoff::retry_notify(
-            self.backoff.clone(),
-            || {
-                self.client.delete(url.clone()).send()?.check_error()?;
-                Ok(())
-            },
-            notify,
-        )?)
+        let client = self.client()?;
+        self.retry(|| {
+            client
+                .delete(url.clone())
+                .send_request()?
+                .check_error(&self.retry_policy)?;
+            Ok(())
+        })
     }
 }
@@ -0,0 +1,215 @@
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::sleep;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use backoff::{backoff::Backoff, Error, ExponentialBackoff, ExponentialBackoffBuilder};
+use chrono::{DateTime, Utc};
+use log::*;
+
+// number of retried requests of all backends
+static RETRIES: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the number of requests that have been retried so far by any backend
+pub fn retry_count() -> u64 {
+    RETRIES.load(Ordering::SeqCst)
+}
+
+/// [`RetryPolicy`] defines if and how often failed requests to remote backends are retried.
+#[derive(Clone, Debug)]
+pub(super) struct RetryPolicy {
+    enabled: bool,
+    max_retries: Option<usize>,
+    initial_interval: Duration,
+    max_interval: Duration,
+    max_elapsed: Option<Duration>,
+    transient_status: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_retries: None,
+            initial_interval: Duration::from_millis(500),
+            max_interval: Duration::from_secs(60),
+            max_elapsed: Some(Duration::from_secs(600)),
+            transient_status: vec![408, 429],
+        }
+    }
+}
+
+fn parse_duration(value: &str) -> Result<Option<Duration>> {
+    Ok(match value {
+        "" | "unlimited" => None,
+        value => Some(*humantime::Duration::from_str(value)?),
+    })
+}
+
+impl RetryPolicy {
+    /// Set a retry option. Returns `false` if `option` is no retry option.
+    pub(super) fn set_option(&mut self, option: &str, value: &str) -> Result<bool> {
+        match option {
+            "retry" => match value {
+                "true" => {
+                    self.enabled = true;
+                    self.max_elapsed = Some(Duration::from_secs(120));
+                }
+                "false" => self.enabled = false,
+                val => bail!("value {val} not supported for option retry!"),
+            },
+            "max-retries" => {
+                self.max_retries = match value {
+                    "" | "unlimited" => None,
+                    value => Some(value.parse()?),
+                };
+            }
+            "retry-initial-interval" => {
+                self.initial_interval = *humantime::Duration::from_str(value)?;
+            }
+            "retry-max-interval" => self.max_interval = *humantime::Duration::from_str(value)?,
+            "retry-max-elapsed" => self.max_elapsed = parse_duration(value)?,
+            "retry-status-codes" => {
+                self.transient_status = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|code| !code.is_empty())
+                    .map(str::parse)
+                    .collect::<std::result::Result<_, _>>()?;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Returns whether a request which failed with HTTP status `status` should be retried.
+    /// Server errors are always retried, client errors only if configured.
+    pub(super) fn is_transient(&self, status: u16) -> bool {
+        !(400..500).contains(&status) || self.transient_status.contains(&status)
+    }
+
+    fn backoff(&self) -> ExponentialBackoff {
+        ExponentialBackoffBuilder::new()
+            .with_initial_interval(self.initial_interval)
+            .with_max_interval(self.max_interval)
+            .with_max_elapsed_time(self.max_elapsed)
+            .build()
+    }
+
+    /// Run `op` and retry it according to the policy as long as it fails with a transient error.
+    /// A `retry_after` given by the error is used instead of the backoff interval.
+    pub(super) fn retry<T, E: Display + Into<anyhow::Error>>(
+        &self,
+        mut op: impl FnMut() -> std::result::Result<T, Error<E>>,
+    ) -> Result<T> {
+        let mut backoff = self.backoff();
+        let mut retries = 0;
+        loop {
+            let (err, retry_after) = match op() {
+                Ok(t) => return Ok(t),
+                Err(Error::Permanent(err)) => return Err(err.into()),
+                Err(Error::Transient { err, retry_after }) => (err, retry_after),
+            };
+
+            let next = match backoff.next_backoff() {
+                Some(next)
+                    if self.enabled && !matches!(self.max_retries, Some(max) if retries >= max) =>
+                {
+                    next
+                }
+                _ => return Err(err.into()),
+            };
+            let wait = retry_after.unwrap_or(next);
+            retries += 1;
+            let total = RETRIES.fetch_add(1, Ordering::SeqCst) + 1;
+            warn!("Error {err}, retry {retries} in {wait:?} ({total} retries in total)");
+            sleep(wait);
+        }
+    }
+}
+
+/// Parse the value of a `Retry-After` header. It is either given in seconds or as HTTP date.
+pub(super) fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(secs) = value.trim().parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    // a date in the past means retry immediately
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transient(retry_after: Option<Duration>) -> Error<anyhow::Error> {
+        Error::Transient {
+            err: anyhow::anyhow!("failed"),
+            retry_after,
+        }
+    }
+
+    #[test]
+    fn max_retries() -> Result<()> {
+        let mut policy = RetryPolicy::default();
+        policy.set_option("max-retries", "2")?;
+        policy.set_option("retry-initial-interval", "1ms")?;
+        let mut calls = 0;
+        let result: Result<()> = policy.retry(|| {
+            calls += 1;
+            Err(transient(Some(Duration::ZERO)))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn permanent_and_disabled() -> Result<()> {
+        let mut policy = RetryPolicy::default();
+        let mut calls = 0;
+        let result: Result<()> = policy.retry(|| {
+            calls += 1;
+            Err(Error::Permanent(anyhow::anyhow!("failed")))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+
+        policy.set_option("retry", "false")?;
+        let result: Result<()> = policy.retry(|| {
+            calls += 1;
+            Err(transient(None))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn transient_status_codes() -> Result<()> {
+        let mut policy = RetryPolicy::default();
+        assert!(policy.is_transient(429));
+        assert!(policy.is_transient(503));
+        assert!(!policy.is_transient(404));
+        policy.set_option("retry-status-codes", "404, 409")?;
+        assert!(policy.is_transient(404));
+        assert!(!policy.is_transient(429));
+        Ok(())
+    }
+
+    #[test]
+    fn retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
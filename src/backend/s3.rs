@@ -0,0 +1,315 @@
+use std::io::Cursor;
+
+use ::s3::creds::Credentials;
+use ::s3::error::S3Error;
+use ::s3::request::ResponseData;
+use ::s3::{Bucket, Region};
+use anyhow::{anyhow, bail, Result};
+use backoff::Error;
+use bytes::Bytes;
+use log::*;
+
+use super::retry::{parse_retry_after, RetryPolicy};
+use super::{FileType, Id, ReadBackend, WriteBackend};
+
+/// Files larger than this are uploaded using a multipart upload
+const MULTIPART_THRESHOLD: usize = 8 * 1024 * 1024;
+
+// Check the status code of a S3 response and treat errors as permanent or transient
+fn check_status(
+    resp: ResponseData,
+    policy: &RetryPolicy,
+) -> std::result::Result<ResponseData, Error<anyhow::Error>> {
+    match resp.status_code() {
+        200..=299 => Ok(resp),
+        code if policy.is_transient(code) => Err(Error::Transient {
+            err: anyhow!("S3 request failed with status {code}"),
+            retry_after: resp
+                .headers()
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("retry-after"))
+                .and_then(|(_, value)| parse_retry_after(value)),
+        }),
+        code => Err(Error::Permanent(anyhow!(
+            "S3 request failed with status {code}: {}",
+            String::from_utf8_lossy(resp.as_slice())
+        ))),
+    }
+}
+
+// Errors from the S3 client are network errors which may be transient
+fn transient(err: S3Error) -> Error<anyhow::Error> {
+    Error::Transient {
+        err: err.into(),
+        retry_after: None,
+    }
+}
+
+#[derive(Clone)]
+struct S3Settings {
+    endpoint: String,
+    bucket_name: String,
+    prefix: String,
+    region: String,
+    path_style: bool,
+    profile: Option<String>,
+}
+
+impl S3Settings {
+    /// Build the bucket from the settings.
+    /// Credentials are read from the environment (`AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, ..)
+    /// or else from the given profile in the AWS credentials file.
+    fn bucket(&self) -> Result<Bucket> {
+        let region = Region::Custom {
+            region: self.region.clone(),
+            endpoint: self.endpoint.clone(),
+        };
+        let credentials = Credentials::new(None, None, None, None, self.profile.as_deref())?;
+        let bucket = Bucket::new(&self.bucket_name, region, credentials)?;
+        Ok(match self.path_style {
+            true => bucket.with_path_style(),
+            false => bucket,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct S3Backend {
+    settings: S3Settings,
+    bucket: Bucket,
+    retry_policy: RetryPolicy,
+}
+
+impl S3Backend {
+    /// Create a new S3 backend. `url` has the format `[http(s)://]endpoint/bucket[/prefix]`.
+    /// If no scheme is given, https is used.
+    pub fn new(url: &str) -> Result<Self> {
+        let (scheme, url) = url.split_once("://").unwrap_or(("https", url));
+        let mut parts = url.trim_end_matches('/').splitn(3, '/');
+        let (host, bucket_name) = match (parts.next(), parts.next()) {
+            (Some(host), Some(bucket_name)) if !bucket_name.is_empty() => (host, bucket_name),
+            _ => bail!("s3 url must have the format [http(s)://]endpoint/bucket[/prefix]"),
+        };
+
+        let settings = S3Settings {
+            endpoint: format!("{scheme}://{host}"),
+            bucket_name: bucket_name.to_string(),
+            prefix: parts.next().unwrap_or_default().to_string(),
+            region: std::env::var("AWS_DEFAULT_REGION")
+                .or_else(|_| std::env::var("AWS_REGION"))
+                .unwrap_or_else(|_| "us-east-1".to_string()),
+            // virtual-host style only works reliably with AWS itself
+            path_style: !host.ends_with("amazonaws.com"),
+            profile: std::env::var("AWS_PROFILE").ok(),
+        };
+
+        Ok(Self {
+            bucket: settings.bucket()?,
+            settings,
+            retry_policy: RetryPolicy::default(),
+        })
+    }
+
+    fn retry<T>(
+        &self,
+        op: impl FnMut() -> std::result::Result<T, Error<anyhow::Error>>,
+    ) -> Result<T> {
+        self.retry_policy.retry(op)
+    }
+
+    fn dir(&self, tpe: FileType) -> String {
+        match self.settings.prefix.is_empty() {
+            true => tpe.name().to_string(),
+            false => format!("{}/{}", self.settings.prefix, tpe.name()),
+        }
+    }
+
+    fn path(&self, tpe: FileType, id: &Id) -> String {
+        match tpe {
+            FileType::Config => self.dir(tpe),
+            FileType::Pack => {
+                let hex_id = id.to_hex();
+                format!("{}/{}/{}", self.dir(tpe), &hex_id[0..2], hex_id.as_str())
+            }
+            _ => format!("{}/{}", self.dir(tpe), id.to_hex().as_str()),
+        }
+    }
+}
+
+impl ReadBackend for S3Backend {
+    fn location(&self) -> String {
+        let settings = &self.settings;
+        let mut location = format!("s3:{}/{}", settings.endpoint, settings.bucket_name);
+        if !settings.prefix.is_empty() {
+            location.push('/');
+            location.push_str(&settings.prefix);
+        }
+        location
+    }
+
+    fn set_option(&mut self, option: &str, value: &str) -> Result<()> {
+        if self.retry_policy.set_option(option, value)? {
+            return Ok(());
+        }
+
+        match option {
+            "region" => self.settings.region = value.to_string(),
+            "profile" => self.settings.profile = Some(value.to_string()),
+            "path-style" => {
+                self.settings.path_style = match value {
+                    "true" => true,
+                    "false" => false,
+                    val => bail!("value {val} not supported for option path-style!"),
+                };
+            }
+            opt => {
+                warn!("Option {opt} is not supported! Ignoring it.");
+                return Ok(());
+            }
+        }
+        self.bucket = self.settings.bucket()?;
+        Ok(())
+    }
+
+    fn list_with_size(&self, tpe: FileType) -> Result<Vec<(Id, u32)>> {
+        trace!("listing tpe: {tpe:?}");
+        let prefix = match tpe {
+            FileType::Config => self.dir(tpe),
+            _ => self.dir(tpe) + "/",
+        };
+
+        let list = self.retry(|| self.bucket.list(prefix.clone(), None).map_err(transient))?;
+
+        let mut objects = list.into_iter().flat_map(|result| result.contents);
+        if tpe == FileType::Config {
+            return Ok(match objects.any(|obj| obj.key == prefix) {
+                true => vec![(Id::default(), 0)],
+                false => Vec::new(),
+            });
+        }
+
+        objects
+            .filter_map(|obj| {
+                let name = obj.key.rsplit('/').next().unwrap_or_default();
+                Id::from_hex(name).ok().map(|id| (id, obj.size))
+            })
+            .map(|(id, size)| Ok((id, u32::try_from(size)?)))
+            .collect()
+    }
+
+    fn read_full(&self, tpe: FileType, id: &Id) -> Result<Bytes> {
+        trace!("reading tpe: {tpe:?}, id: {id}");
+        let path = self.path(tpe, id);
+        let resp = self.retry(|| {
+            check_status(
+                self.bucket.get_object(&path).map_err(transient)?,
+                &self.retry_policy,
+            )
+        })?;
+        Ok(Bytes::copy_from_slice(resp.as_slice()))
+    }
+
+    fn read_partial(
+        &self,
+        tpe: FileType,
+        id: &Id,
+        _cacheable: bool,
+        offset: u32,
+        length: u32,
+    ) -> Result<Bytes> {
+        trace!("reading tpe: {tpe:?}, id: {id}, offset: {offset}, length: {length}");
+        // the HTTP range is inclusive, so an empty range can't be requested
+        if length == 0 {
+            return Ok(Bytes::new());
+        }
+        let path = self.path(tpe, id);
+        let start = u64::from(offset);
+        let end = start + u64::from(length) - 1;
+        let resp = self.retry(|| {
+            check_status(
+                self.bucket
+                    .get_object_range(&path, start, Some(end))
+                    .map_err(transient)?,
+                &self.retry_policy,
+            )
+        })?;
+        Ok(Bytes::copy_from_slice(resp.as_slice()))
+    }
+}
+
+impl WriteBackend for S3Backend {
+    fn create(&self) -> Result<()> {
+        // S3 has no directories, so there is nothing to create
+        Ok(())
+    }
+
+    fn write_bytes(&self, tpe: FileType, id: &Id, _cacheable: bool, buf: Bytes) -> Result<()> {
+        trace!("writing tpe: {:?}, id: {}", &tpe, &id);
+        let path = self.path(tpe, id);
+        self.retry(|| {
+            if buf.len() > MULTIPART_THRESHOLD {
+                let status = self
+                    .bucket
+                    .put_object_stream(&mut Cursor::new(&buf), &path)
+                    .map_err(transient)?;
+                if !(200..300).contains(&status) {
+                    return Err(Error::Transient {
+                        err: anyhow!("S3 multipart upload failed with status {status}"),
+                        retry_after: None,
+                    });
+                }
+            } else {
+                check_status(
+                    self.bucket.put_object(&path, &buf).map_err(transient)?,
+                    &self.retry_policy,
+                )?;
+            }
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    fn remove(&self, tpe: FileType, id: &Id, _cacheable: bool) -> Result<()> {
+        trace!("removing tpe: {:?}, id: {}", &tpe, &id);
+        let path = self.path(tpe, id);
+        self.retry(|| {
+            check_status(
+                self.bucket.delete_object(&path).map_err(transient)?,
+                &self.retry_policy,
+            )
+        })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::*;
+
+    // Needs an existing bucket: set RUSTIC_TEST_S3_URL (e.g. to http://localhost:9000/bucket/prefix)
+    // and the AWS credentials and run with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn s3_round_trip() -> Result<()> {
+        let url = std::env::var("RUSTIC_TEST_S3_URL").context("RUSTIC_TEST_S3_URL not set")?;
+        let be = S3Backend::new(&url)?;
+        let id = Id::random();
+        be.write_bytes(FileType::Pack, &id, false, Bytes::from_static(b"test data"))?;
+
+        assert!(be.list(FileType::Pack)?.contains(&id));
+        assert_eq!(be.read_full(FileType::Pack, &id)?, &b"test data"[..]);
+        assert_eq!(
+            be.read_partial(FileType::Pack, &id, false, 5, 4)?,
+            &b"data"[..]
+        );
+        assert!(be
+            .read_partial(FileType::Pack, &id, false, 5, 0)?
+            .is_empty());
+
+        be.remove(FileType::Pack, &id, false)?;
+        assert!(!be.list(FileType::Pack)?.contains(&id));
+        Ok(())
+    }
+}
@@ -0,0 +1,352 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use crossbeam_channel::{bounded, Receiver, Sender};
+use log::*;
+use ssh2::{CheckResult, ErrorCode, KnownHostFileKind, Session, Sftp};
+
+use super::{FileType, Id, ReadBackend, WriteBackend, ALL_FILE_TYPES};
+
+#[derive(Clone)]
+struct SftpSettings {
+    user: String,
+    host: String,
+    port: u16,
+    identity_file: Option<PathBuf>,
+    known_hosts: Option<PathBuf>,
+    connections: usize,
+}
+
+impl SftpSettings {
+    /// Open a new SFTP connection: verify the host key against the known hosts file and
+    /// authenticate using the given identity file or else the ssh agent or the default keys.
+    fn connect(&self) -> Result<Sftp> {
+        debug!("opening sftp connection to {}:{}", self.host, self.port);
+        let tcp = TcpStream::connect((self.host.as_str(), self.port))?;
+        let mut session = Session::new()?;
+        session.set_tcp_stream(tcp);
+        session.handshake()?;
+        self.verify_host_key(&session)?;
+
+        match &self.identity_file {
+            Some(key) => session
+                .userauth_pubkey_file(&self.user, None, key, None)
+                .with_context(|| format!("key {key:?} is not accepted"))?,
+            None => {
+                if session.userauth_agent(&self.user).is_err() {
+                    let ssh_dir = dirs::home_dir().unwrap_or_default().join(".ssh");
+                    for key in ["id_ed25519", "id_ecdsa", "id_rsa"] {
+                        let key = ssh_dir.join(key);
+                        if key.exists()
+                            && session
+                                .userauth_pubkey_file(&self.user, None, &key, None)
+                                .is_ok()
+                        {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        if !session.authenticated() {
+            bail!("authentication as {} at {} failed", self.user, self.host);
+        }
+        Ok(session.sftp()?)
+    }
+
+    fn verify_host_key(&self, session: &Session) -> Result<()> {
+        let file = match &self.known_hosts {
+            Some(file) => file.clone(),
+            None => dirs::home_dir()
+                .ok_or_else(|| anyhow!("cannot determine home dir for known_hosts"))?
+                .join(".ssh")
+                .join("known_hosts"),
+        };
+        let mut known_hosts = session.known_hosts()?;
+        known_hosts
+            .read_file(&file, KnownHostFileKind::OpenSSH)
+            .with_context(|| format!("error reading known hosts file {file:?}"))?;
+        let (key, _) = session
+            .host_key()
+            .ok_or_else(|| anyhow!("no host key received from {}", self.host))?;
+        match known_hosts.check_port(&self.host, self.port, key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::NotFound => bail!("host {} is not contained in {file:?}", self.host),
+            CheckResult::Mismatch => bail!(
+                "host key of {} does not match the key in {file:?}! This may be an attack.",
+                self.host
+            ),
+            CheckResult::Failure => bail!("error checking host key of {}", self.host),
+        }
+    }
+}
+
+/// [`Pool`] holds up to `settings.connections` SFTP connections which are created lazily
+struct Pool {
+    settings: SftpSettings,
+    sender: Sender<Sftp>,
+    receiver: Receiver<Sftp>,
+    created: Mutex<usize>,
+}
+
+impl Pool {
+    fn new(settings: SftpSettings) -> Self {
+        let (sender, receiver) = bounded(settings.connections);
+        Self {
+            settings,
+            sender,
+            receiver,
+            created: Mutex::new(0),
+        }
+    }
+
+    /// Run `f` on a connection from the pool. If it fails because the connection is broken,
+    /// the connection is dropped and a new one will be created when needed.
+    fn with_connection<T>(&self, f: impl FnOnce(&Sftp) -> Result<T>) -> Result<T> {
+        let sftp = loop {
+            if let Ok(sftp) = self.receiver.try_recv() {
+                break sftp;
+            }
+            let mut created = self.created.lock().unwrap();
+            if *created < self.settings.connections {
+                // reserve the connection, but don't block other threads while connecting
+                *created += 1;
+                drop(created);
+                match self.settings.connect() {
+                    Ok(sftp) => break sftp,
+                    Err(err) => {
+                        *self.created.lock().unwrap() -= 1;
+                        return Err(err);
+                    }
+                }
+            }
+            drop(created);
+            // wait for a connection to be returned; recheck as broken connections are not returned
+            if let Ok(sftp) = self.receiver.recv_timeout(Duration::from_millis(100)) {
+                break sftp;
+            }
+        };
+
+        let result = f(&sftp);
+        match &result {
+            Err(err) if is_transport_error(err) => *self.created.lock().unwrap() -= 1,
+            _ => self.sender.send(sftp)?,
+        }
+        result
+    }
+}
+
+/// Returns if the error means that the connection is broken. In contrast, errors of single
+/// SFTP operations (like a missing file) leave the connection usable.
+fn is_transport_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        let io_err = cause.downcast_ref::<io::Error>();
+        let ssh_err = cause.downcast_ref::<ssh2::Error>().or_else(|| {
+            io_err
+                .and_then(io::Error::get_ref)
+                .and_then(|err| err.downcast_ref::<ssh2::Error>())
+        });
+        match (ssh_err, io_err) {
+            (Some(err), _) => matches!(err.code(), ErrorCode::Session(_)),
+            (None, Some(err)) => matches!(
+                err.kind(),
+                io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::TimedOut
+            ),
+            (None, None) => false,
+        }
+    })
+}
+
+#[derive(Clone)]
+pub struct SftpBackend {
+    path: PathBuf,
+    settings: SftpSettings,
+    pool: Arc<Pool>,
+}
+
+impl SftpBackend {
+    /// Create a new SFTP backend. `url` has the format `[user@]host[:port]:/path`
+    pub fn new(url: &str) -> Result<Self> {
+        let (user, url) = match url.split_once('@') {
+            Some((user, url)) => (user.to_string(), url),
+            None => (whoami()?, url),
+        };
+        let (host, path) = url
+            .split_once(':')
+            .ok_or_else(|| anyhow!("sftp url must have the format [user@]host[:port]:/path"))?;
+        let (port, path) = match path.split_once(':') {
+            Some((port, path)) if port.chars().all(|c| c.is_ascii_digit()) => (port.parse()?, path),
+            _ => (22, path),
+        };
+
+        let settings = SftpSettings {
+            user,
+            host: host.to_string(),
+            port,
+            identity_file: None,
+            known_hosts: None,
+            connections: 5,
+        };
+        Ok(Self {
+            path: path.into(),
+            pool: Arc::new(Pool::new(settings.clone())),
+            settings,
+        })
+    }
+
+    fn path(&self, tpe: FileType, id: &Id) -> PathBuf {
+        let hex_id = id.to_hex();
+        match tpe {
+            FileType::Config => self.path.join("config"),
+            FileType::Pack => self.path.join("data").join(&hex_id[0..2]).join(hex_id),
+            _ => self.path.join(tpe.name()).join(hex_id),
+        }
+    }
+}
+
+fn whoami() -> Result<String> {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .map_err(|_| anyhow!("no user given in sftp url"))
+}
+
+/// List all files below `dir` (recursively) together with their sizes
+fn list_files(sftp: &Sftp, dir: &Path) -> Result<Vec<(PathBuf, u64)>> {
+    let mut files = Vec::new();
+    for (path, stat) in sftp.readdir(dir)? {
+        if stat.is_dir() {
+            files.extend(list_files(sftp, &path)?);
+        } else if stat.is_file() {
+            files.push((path, stat.size.unwrap_or_default()));
+        }
+    }
+    Ok(files)
+}
+
+impl ReadBackend for SftpBackend {
+    fn location(&self) -> String {
+        format!(
+            "sftp:{}@{}:{}:{}",
+            self.settings.user,
+            self.settings.host,
+            self.settings.port,
+            self.path.display()
+        )
+    }
+
+    fn set_option(&mut self, option: &str, value: &str) -> Result<()> {
+        match option {
+            "identity-file" => self.settings.identity_file = Some(value.into()),
+            "known-hosts" => self.settings.known_hosts = Some(value.into()),
+            "connections" => {
+                self.settings.connections = value.parse()?;
+                if self.settings.connections == 0 {
+                    bail!("connections must be at least 1");
+                }
+            }
+            opt => {
+                warn!("Option {opt} is not supported! Ignoring it.");
+                return Ok(());
+            }
+        }
+        self.pool = Arc::new(Pool::new(self.settings.clone()));
+        Ok(())
+    }
+
+    fn list_with_size(&self, tpe: FileType) -> Result<Vec<(Id, u32)>> {
+        trace!("listing tpe: {tpe:?}");
+        self.pool.with_connection(|sftp| {
+            if tpe == FileType::Config {
+                return Ok(match sftp.stat(&self.path.join("config")) {
+                    Ok(stat) => vec![(Id::default(), stat.size.unwrap_or_default().try_into()?)],
+                    Err(_) => Vec::new(),
+                });
+            }
+
+            Ok(list_files(sftp, &self.path.join(tpe.name()))?
+                .into_iter()
+                .filter_map(|(path, size)| {
+                    let id = Id::from_hex(&path.file_name()?.to_string_lossy()).ok()?;
+                    Some((id, size.try_into().ok()?))
+                })
+                .collect())
+        })
+    }
+
+    fn read_full(&self, tpe: FileType, id: &Id) -> Result<Bytes> {
+        trace!("reading tpe: {tpe:?}, id: {id}");
+        self.pool.with_connection(|sftp| {
+            let mut vec = Vec::new();
+            sftp.open(self.path(tpe, id))?.read_to_end(&mut vec)?;
+            Ok(vec.into())
+        })
+    }
+
+    fn read_partial(
+        &self,
+        tpe: FileType,
+        id: &Id,
+        _cacheable: bool,
+        offset: u32,
+        length: u32,
+    ) -> Result<Bytes> {
+        trace!("reading tpe: {tpe:?}, id: {id}, offset: {offset}, length: {length}");
+        self.pool.with_connection(|sftp| {
+            let mut file = sftp.open(self.path(tpe, id))?;
+            file.seek(SeekFrom::Start(offset.into()))?;
+            let mut vec = vec![0; length.try_into()?];
+            file.read_exact(&mut vec)?;
+            Ok(vec.into())
+        })
+    }
+}
+
+impl WriteBackend for SftpBackend {
+    fn create(&self) -> Result<()> {
+        trace!("creating repo at {:?}", self.path);
+        self.pool.with_connection(|sftp| {
+            let mkdir = |path: &Path| {
+                if sftp.stat(path).is_err() {
+                    sftp.mkdir(path, 0o700)?;
+                }
+                Ok::<_, anyhow::Error>(())
+            };
+
+            mkdir(&self.path)?;
+            for tpe in ALL_FILE_TYPES {
+                mkdir(&self.path.join(tpe.name()))?;
+            }
+            for i in 0u8..=255 {
+                mkdir(&self.path.join("data").join(hex::encode([i])))?;
+            }
+            Ok(())
+        })
+    }
+
+    fn write_bytes(&self, tpe: FileType, id: &Id, _cacheable: bool, buf: Bytes) -> Result<()> {
+        trace!("writing tpe: {:?}, id: {}", &tpe, &id);
+        self.pool.with_connection(|sftp| {
+            let mut file = sftp.create(&self.path(tpe, id))?;
+            file.write_all(&buf)?;
+            // fsync is an extension which is not supported by all servers
+            if let Err(err) = file.fsync() {
+                debug!("fsync not possible: {err}");
+            }
+            Ok(())
+        })
+    }
+
+    fn remove(&self, tpe: FileType, id: &Id, _cacheable: bool) -> Result<()> {
+        trace!("removing tpe: {:?}, id: {}", &tpe, &id);
+        self.pool
+            .with_connection(|sftp| Ok(sftp.unlink(&self.path(tpe, id))?))
+    }
+}
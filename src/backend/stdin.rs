@@ -1,25 +1,54 @@
 use std::io::{stdin, Stdin};
 use std::path::PathBuf;
+use std::process::{Child, ChildStdout, Command, Stdio};
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
+
+use crate::repository::parse_command;
 
 use super::{node::Metadata, node::NodeType, Node, ReadSource};
 use super::{ReadSourceEntry, ReadSourceOpen};
 
-pub struct StdinSource {
-    finished: bool,
+/// [`StdinSource`] yields a single file with the content read from stdin
+/// or from the output of a command.
+pub struct StdinSource<O: ReadSourceOpen = OpenStdin> {
     path: PathBuf,
+    open: Option<O>,
 }
 
 impl StdinSource {
     pub fn new(path: PathBuf) -> Result<Self> {
         Ok(Self {
-            finished: false,
             path,
+            open: Some(OpenStdin()),
         })
     }
 }
 
+impl StdinSource<OpenCommand> {
+    /// Spawn `command` and use its stdout as content. The returned [`Child`] must be
+    /// waited for after the content has been read.
+    pub fn from_command(path: PathBuf, command: &str) -> Result<(Self, Child)> {
+        let args = parse_command::<()>(command)?.1;
+        let (program, args) = args
+            .split_first()
+            .ok_or_else(|| anyhow!("stdin-command must not be empty"))?;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to call stdin-command {command}"))?;
+        let stdout = child.stdout.take().unwrap();
+        Ok((
+            Self {
+                path,
+                open: Some(OpenCommand(stdout)),
+            },
+            child,
+        ))
+    }
+}
+
 pub struct OpenStdin();
 
 impl ReadSourceOpen for OpenStdin {
@@ -30,8 +59,18 @@ impl ReadSourceOpen for OpenStdin {
     }
 }
 
-impl ReadSource for StdinSource {
-    type Open = OpenStdin;
+pub struct OpenCommand(ChildStdout);
+
+impl ReadSourceOpen for OpenCommand {
+    type Reader = ChildStdout;
+
+    fn open(self) -> Result<Self::Reader> {
+        Ok(self.0)
+    }
+}
+
+impl<O: ReadSourceOpen> ReadSource for StdinSource<O> {
+    type Open = O;
     type Iter = Self;
 
     fn size(&self) -> Result<Option<u64>> {
@@ -43,14 +82,11 @@ impl ReadSource for StdinSource {
     }
 }
 
-impl Iterator for StdinSource {
-    type Item = Result<ReadSourceEntry<OpenStdin>>;
+impl<O: ReadSourceOpen> Iterator for StdinSource<O> {
+    type Item = Result<ReadSourceEntry<O>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.finished {
-            return None;
-        }
-        self.finished = true;
+        let open = self.open.take()?;
 
         Some(Ok(ReadSourceEntry {
             path: self.path.clone(),
@@ -59,7 +95,7 @@ impl Iterator for StdinSource {
                 NodeType::File,
                 Metadata::default(),
             ),
-            open: Some(OpenStdin()),
+            open: Some(open),
         }))
     }
 }
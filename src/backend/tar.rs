@@ -0,0 +1,411 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{copy, stdin, BufRead, BufReader, Read, Seek, SeekFrom, Take};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{Local, TimeZone, Utc};
+use flate2::read::GzDecoder;
+use log::*;
+use tar::{Archive, EntryType, Header};
+use tempfile::{NamedTempFile, TempPath};
+
+use super::node::{Metadata, NodeType};
+use super::{Node, ReadSource, ReadSourceEntry, ReadSourceOpen};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// The uncompressed tar archive: either the given file or a temporary copy
+enum TarFile {
+    Given(PathBuf),
+    Temp(TempPath),
+}
+
+impl TarFile {
+    fn path(&self) -> &Path {
+        match self {
+            Self::Given(path) => path,
+            Self::Temp(path) => path,
+        }
+    }
+}
+
+/// [`TarSource`] yields the entries of a tar archive which is read from a file or from stdin.
+/// Compressed archives (gzip or zstd) and archives read from stdin are first uncompressed
+/// into a temporary file, so that the contents of all entries can be read in any order.
+pub struct TarSource {
+    size: u64,
+    entries: Vec<ReadSourceEntry<OpenTar>>,
+}
+
+impl TarSource {
+    /// Read the tar archive from `file` or from stdin if `file` is `None`.
+    /// All entries are put below `base`.
+    pub fn new(file: Option<&Path>, base: &Path) -> Result<Self> {
+        let mut reader: BufReader<Box<dyn Read>> = BufReader::new(match file {
+            Some(file) => Box::new(
+                File::open(file).with_context(|| format!("Unable to open {}", file.display()))?,
+            ),
+            None => Box::new(stdin()),
+        });
+        let (gzip, zstd) = {
+            let magic = reader.fill_buf()?;
+            (magic.starts_with(GZIP_MAGIC), magic.starts_with(ZSTD_MAGIC))
+        };
+
+        let tar_file = match file {
+            Some(file) if !gzip && !zstd => TarFile::Given(file.to_path_buf()),
+            _ => {
+                let mut reader: Box<dyn Read> = if gzip {
+                    Box::new(GzDecoder::new(reader))
+                } else if zstd {
+                    Box::new(zstd::Decoder::with_buffer(reader)?)
+                } else {
+                    Box::new(reader)
+                };
+                let mut temp = NamedTempFile::new()?;
+                copy(&mut reader, &mut temp)?;
+                TarFile::Temp(temp.into_temp_path())
+            }
+        };
+        let tar_file = Arc::new(tar_file);
+
+        let mut archive = Archive::new(FixHeaders::new(File::open(tar_file.path())?));
+        let mut entries = BTreeMap::new();
+        // location of the contents of all regular files, needed for hard links
+        let mut contents = HashMap::new();
+        let mut size = 0;
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let path = normalize(&entry.path()?);
+            // ignore the root dir
+            let name = match path.file_name() {
+                Some(name) => name.to_os_string(),
+                None => continue,
+            };
+            let header = entry.header();
+            let mut meta = metadata(header);
+            let mut open = None;
+
+            let node_type = match header.entry_type() {
+                EntryType::Directory => NodeType::Dir,
+                EntryType::Regular | EntryType::Continuous => {
+                    let content = (entry.raw_file_position(), entry.size());
+                    contents.insert(path.clone(), content);
+                    open = Some(content);
+                    NodeType::File
+                }
+                EntryType::Link => {
+                    let target = normalize(&link_name(&entry)?);
+                    match contents.get(&target) {
+                        Some(content) => {
+                            open = Some(*content);
+                            NodeType::File
+                        }
+                        None => {
+                            warn!("ignoring hard link {path:?} to unknown file {target:?}");
+                            continue;
+                        }
+                    }
+                }
+                EntryType::Symlink => NodeType::Symlink {
+                    linktarget: link_name(&entry)?.to_string_lossy().to_string(),
+                },
+                EntryType::Block => NodeType::Dev {
+                    device: device(header)?,
+                },
+                EntryType::Char => NodeType::Chardev {
+                    device: device(header)?,
+                },
+                EntryType::Fifo => NodeType::Fifo,
+                tpe => {
+                    warn!("ignoring {path:?} with unsupported entry type {tpe:?}");
+                    continue;
+                }
+            };
+
+            if let Some((_, length)) = open {
+                meta.size = length;
+                size += length;
+            }
+            let node = Node::new_node(&name, node_type, meta);
+            let open = open.map(|(offset, length)| OpenTar {
+                file: tar_file.clone(),
+                offset,
+                length,
+            });
+            let path = base.join(path);
+            // later entries replace earlier ones with the same path
+            entries.insert(path.clone(), ReadSourceEntry { path, node, open });
+        }
+
+        Ok(Self {
+            size,
+            entries: entries.into_values().collect(),
+        })
+    }
+}
+
+const BLOCK_SIZE: usize = 512;
+
+/// [`FixHeaders`] passes a tar archive through, but fills empty size fields of headers with 0.
+/// Some tools leave the size empty for entries without content (e.g. links), which the tar crate
+/// rejects. As only header fields are changed, all positions within the archive are kept.
+struct FixHeaders<R> {
+    inner: R,
+    block: [u8; BLOCK_SIZE],
+    pos: usize,
+    filled: usize,
+    // number of data blocks until the next header
+    data_blocks: u64,
+    // the next block is an extension of a GNU sparse header
+    sparse_extension: bool,
+}
+
+impl<R: Read> FixHeaders<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            block: [0; BLOCK_SIZE],
+            pos: 0,
+            filled: 0,
+            data_blocks: 0,
+            sparse_extension: false,
+        }
+    }
+
+    fn process_block(&mut self) {
+        let block = &mut self.block;
+        if self.data_blocks > 0 {
+            self.data_blocks -= 1;
+        } else if self.sparse_extension {
+            self.sparse_extension = block[504] != 0;
+        } else if block.iter().any(|b| *b != 0) {
+            let size = &mut block[124..136];
+            if size.iter().all(|b| *b == 0 || *b == b' ') {
+                size.copy_from_slice(b"00000000000\0");
+                // recompute the checksum, which is computed with the checksum field set to spaces
+                block[148..156].fill(b' ');
+                let sum: u32 = block.iter().map(|b| u32::from(*b)).sum();
+                block[148..156].copy_from_slice(format!("{sum:06o}\0 ").as_bytes());
+            }
+            // entries with an invalid size are rejected by the tar crate
+            let size = Header::from_byte_slice(block)
+                .entry_size()
+                .unwrap_or_default();
+            self.data_blocks = size.div_ceil(BLOCK_SIZE as u64);
+            self.sparse_extension = block[156] == b'S' && block[482] != 0;
+        }
+    }
+}
+
+impl<R: Read> Read for FixHeaders<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos == self.filled {
+            self.pos = 0;
+            self.filled = 0;
+            while self.filled < BLOCK_SIZE {
+                match self.inner.read(&mut self.block[self.filled..])? {
+                    0 => break,
+                    n => self.filled += n,
+                }
+            }
+            if self.filled == BLOCK_SIZE {
+                self.process_block();
+            }
+        }
+        let len = buf.len().min(self.filled - self.pos);
+        buf[..len].copy_from_slice(&self.block[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// Only keep the normal components of a path within the archive, i.e. remove `/`, `.` and `..`
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|comp| matches!(comp, Component::Normal(_)))
+        .collect()
+}
+
+fn link_name<R: Read>(entry: &tar::Entry<'_, R>) -> Result<PathBuf> {
+    Ok(entry
+        .link_name()?
+        .ok_or_else(|| anyhow!("link without target in tar archive"))?
+        .into_owned())
+}
+
+fn metadata(header: &Header) -> Metadata {
+    let mtime = header
+        .mtime()
+        .ok()
+        .and_then(|mtime| Utc.timestamp_opt(mtime.try_into().ok()?, 0).single())
+        .map(|dt| dt.with_timezone(&Local));
+
+    Metadata {
+        mode: header
+            .mode()
+            .ok()
+            .and_then(|mode| go_mode(mode, header.entry_type())),
+        mtime,
+        atime: mtime,
+        uid: header.uid().ok().and_then(|uid| uid.try_into().ok()),
+        gid: header.gid().ok().and_then(|gid| gid.try_into().ok()),
+        user: header.username().ok().flatten().map(str::to_string),
+        group: header.groupname().ok().flatten().map(str::to_string),
+        ..Default::default()
+    }
+}
+
+#[cfg(not(windows))]
+fn go_mode(mode: u32, entry_type: EntryType) -> Option<u32> {
+    let file_type = match entry_type {
+        EntryType::Directory => 0o040000,
+        EntryType::Symlink => 0o120000,
+        EntryType::Block => 0o060000,
+        EntryType::Char => 0o020000,
+        EntryType::Fifo => 0o010000,
+        _ => 0o100000,
+    };
+    Some(super::ignore::mapper::map_mode_to_go(
+        (mode & 0o7777) | file_type,
+    ))
+}
+
+#[cfg(windows)]
+fn go_mode(_mode: u32, _entry_type: EntryType) -> Option<u32> {
+    None
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn device(header: &Header) -> Result<u64> {
+    let major = header.device_major()?.unwrap_or_default();
+    let minor = header.device_minor()?.unwrap_or_default();
+    Ok(nix::sys::stat::makedev(major.into(), minor.into()))
+}
+
+// TODO: compute device ids for other OSes
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn device(_header: &Header) -> Result<u64> {
+    Ok(0)
+}
+
+/// [`OpenTar`] reads the content of a file entry from the uncompressed tar archive
+pub struct OpenTar {
+    file: Arc<TarFile>,
+    offset: u64,
+    length: u64,
+}
+
+impl ReadSourceOpen for OpenTar {
+    type Reader = Take<File>;
+
+    fn open(self) -> Result<Self::Reader> {
+        let mut file = File::open(self.file.path())?;
+        file.seek(SeekFrom::Start(self.offset))?;
+        Ok(file.take(self.length))
+    }
+}
+
+impl ReadSource for TarSource {
+    type Open = OpenTar;
+    type Iter = std::iter::Map<
+        std::vec::IntoIter<ReadSourceEntry<OpenTar>>,
+        fn(ReadSourceEntry<OpenTar>) -> Result<ReadSourceEntry<OpenTar>>,
+    >;
+
+    fn size(&self) -> Result<Option<u64>> {
+        Ok(Some(self.size))
+    }
+
+    fn entries(self) -> Self::Iter {
+        self.entries.into_iter().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(entry_type: EntryType, size: u64) -> Header {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_size(size);
+        header.set_mode(0o640);
+        header.set_mtime(1_600_000_000);
+        header
+    }
+
+    #[test]
+    fn read_tar() -> Result<()> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut file = header(EntryType::Regular, 4);
+        file.set_uid(1000);
+        builder.append_data(&mut file, "./dir/file", &b"data"[..])?;
+        builder.append_link(&mut header(EntryType::Symlink, 0), "dir/link", "file")?;
+        builder.append_link(&mut header(EntryType::Link, 0), "a_hardlink", "dir/file")?;
+
+        let mut temp = NamedTempFile::new()?;
+        std::io::Write::write_all(&mut temp, &builder.into_inner()?)?;
+        let src = TarSource::new(Some(temp.path()), Path::new("/base"))?;
+        assert_eq!(src.size()?, Some(8));
+
+        let entries: Vec<_> = src.entries().collect::<Result<_>>()?;
+        let paths: Vec<_> = entries.iter().map(|entry| entry.path.clone()).collect();
+        assert_eq!(
+            paths,
+            ["/base/a_hardlink", "/base/dir/file", "/base/dir/link"].map(PathBuf::from)
+        );
+        let file = &entries[1].node;
+        assert_eq!(file.meta.uid, Some(1000));
+        assert_eq!(file.meta.mtime.unwrap().timestamp(), 1_600_000_000);
+        assert_eq!(
+            entries[2].node.node_type,
+            NodeType::Symlink {
+                linktarget: "file".to_string()
+            }
+        );
+
+        let mut content = String::new();
+        for entry in entries {
+            if let Some(open) = entry.open {
+                open.open()?.read_to_string(&mut content)?;
+            }
+        }
+        assert_eq!(content, "datadata");
+        Ok(())
+    }
+
+    #[test]
+    fn read_tar_link_without_size() -> Result<()> {
+        let mut builder = tar::Builder::new(Vec::new());
+        builder.append_data(&mut header(EntryType::Regular, 4), "file", &b"data"[..])?;
+        // the size field of this header stays empty
+        let mut link = Header::new_gnu();
+        link.set_entry_type(EntryType::Symlink);
+        builder.append_link(&mut link, "link", "file")?;
+        builder.append_data(&mut header(EntryType::Regular, 4), "other", &b"more"[..])?;
+
+        let mut temp = NamedTempFile::new()?;
+        std::io::Write::write_all(&mut temp, &builder.into_inner()?)?;
+        let src = TarSource::new(Some(temp.path()), Path::new("/base"))?;
+        let mut entries: Vec<_> = src.entries().collect::<Result<_>>()?;
+        let paths: Vec<_> = entries.iter().map(|entry| entry.path.clone()).collect();
+        assert_eq!(
+            paths,
+            ["/base/file", "/base/link", "/base/other"].map(PathBuf::from)
+        );
+
+        let mut content = String::new();
+        entries[2]
+            .open
+            .take()
+            .unwrap()
+            .open()?
+            .read_to_string(&mut content)?;
+        assert_eq!(content, "more");
+        Ok(())
+    }
+}
@@ -162,31 +162,52 @@ where
     }
 
     pub fn new_with_glob(be: BE, node: &Node, opts: TreeStreamerOptions) -> Result<Self> {
+        let overrides = opts.overrides()?;
+        Self::new_streamer(be, node, Some(overrides))
+    }
+}
+
+impl TreeStreamerOptions {
+    /// Build the glob overrides. Paths are matched relative to the root of the streamed tree.
+    pub fn overrides(self) -> Result<Override> {
+        self.build_overrides(false)
+    }
+
+    /// Build the glob overrides only from the exclude globs, i.e. the globs starting with `!`.
+    pub fn exclude_overrides(self) -> Result<Override> {
+        self.build_overrides(true)
+    }
+
+    fn build_overrides(self, only_excludes: bool) -> Result<Override> {
         let mut override_builder = OverrideBuilder::new("/");
+        let add = |builder: &mut OverrideBuilder, glob: &str| -> Result<()> {
+            if !only_excludes || glob.starts_with('!') {
+                builder.add(glob)?;
+            }
+            Ok(())
+        };
 
-        for g in opts.glob {
-            override_builder.add(&g)?;
+        for g in self.glob {
+            add(&mut override_builder, &g)?;
         }
 
-        for file in opts.glob_file {
+        for file in self.glob_file {
             for line in std::fs::read_to_string(file)?.lines() {
-                override_builder.add(line)?;
+                add(&mut override_builder, line)?;
             }
         }
 
         override_builder.case_insensitive(true)?;
-        for g in opts.iglob {
-            override_builder.add(&g)?;
+        for g in self.iglob {
+            add(&mut override_builder, &g)?;
         }
 
-        for file in opts.iglob_file {
+        for file in self.iglob_file {
             for line in std::fs::read_to_string(file)?.lines() {
-                override_builder.add(line)?;
+                add(&mut override_builder, line)?;
             }
         }
-        let overrides = override_builder.build()?;
-
-        Self::new_streamer(be, node, Some(overrides))
+        Ok(override_builder.build()?)
     }
 }
 
@@ -239,7 +260,7 @@ where
 pub struct TreeStreamerOnce {
     visited: HashSet<Id>,
     queue_in: Option<Sender<(PathBuf, Id, usize)>>,
-    queue_out: Receiver<Result<(PathBuf, Tree, usize)>>,
+    queue_out: Receiver<Result<(PathBuf, Tree, Id, usize)>>,
     p: ProgressBar,
     counter: Vec<usize>,
     finished_ids: usize,
@@ -261,7 +282,7 @@ impl TreeStreamerOnce {
             std::thread::spawn(move || {
                 for (path, id, count) in in_rx {
                     out_tx
-                        .send(Tree::from_backend(&be, id).map(|tree| (path, tree, count)))
+                        .send(Tree::from_backend(&be, id).map(|tree| (path, tree, id, count)))
                         .unwrap();
                 }
             });
@@ -298,7 +319,7 @@ impl TreeStreamerOnce {
     }
 }
 
-type TreeStreamItem = Result<(PathBuf, Tree)>;
+type TreeStreamItem = Result<(PathBuf, Tree, Id)>;
 
 impl Iterator for TreeStreamerOnce {
     type Item = TreeStreamItem;
@@ -309,7 +330,7 @@ impl Iterator for TreeStreamerOnce {
             self.p.finish();
             return None;
         }
-        let (path, tree, count) = match self.queue_out.recv() {
+        let (path, tree, id, count) = match self.queue_out.recv() {
             Ok(Ok(res)) => res,
             Err(err) => return Some(Err(err.into())),
             Ok(Err(err)) => return Some(Err(err)),
@@ -330,7 +351,7 @@ impl Iterator for TreeStreamerOnce {
             self.p.inc(1);
             self.finished_ids += 1;
         }
-        Some(Ok((path, tree)))
+        Some(Ok((path, tree, id)))
     }
 }
 
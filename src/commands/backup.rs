@@ -1,24 +1,27 @@
 use std::path::PathBuf;
+use std::process::Command;
 use std::str::FromStr;
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use chrono::Local;
 use clap::Parser;
 use log::*;
 use merge::Merge;
 use path_dedot::ParseDot;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use super::{bytes, progress_bytes, progress_counter, Config};
 use crate::archiver::Archiver;
 use crate::backend::{
-    DryRunBackend, LocalSource, LocalSourceFilterOptions, LocalSourceSaveOptions, StdinSource,
+    DecryptWriteBackend, DryRunBackend, FilesSource, LocalSource, LocalSourceFilterOptions,
+    LocalSourceSaveOptions, StdinSource, TarSource,
 };
+use crate::id::Id;
 use crate::index::IndexBackend;
 use crate::repofile::{
-    PathList, SnapshotFile, SnapshotGroup, SnapshotGroupCriterion, SnapshotOptions,
+    PathList, SnapshotFile, SnapshotGroup, SnapshotGroupCriterion, SnapshotOptions, SnapshotSummary,
 };
-use crate::repository::OpenRepository;
+use crate::repository::{parse_command, OpenRepository};
 
 #[derive(Clone, Default, Debug, Parser, Deserialize, Merge)]
 #[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
@@ -81,11 +84,54 @@ pub struct Opts {
     #[merge(strategy = merge::bool::overwrite_false)]
     ignore_inode: bool,
 
+    /// Don't save the snapshot if nothing changed compared to the parent snapshot
+    #[clap(
+        long,
+        conflicts_with = "force",
+        help_heading = "Options for parent processing"
+    )]
+    #[merge(strategy = merge::bool::overwrite_false)]
+    skip_if_unchanged: bool,
+
     /// Set filename to be used when backing up from stdin
     #[clap(long, value_name = "FILENAME", default_value = "stdin")]
     #[merge(skip)]
     stdin_filename: String,
 
+    /// Run this command and backup its output like stdin. The backup fails if the command fails.
+    #[clap(long, value_name = "COMMAND", conflicts_with = "cli_sources")]
+    stdin_command: Option<String>,
+
+    /// Backup the contents of the given tar archive (optionally compressed), use - for stdin
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = &["stdin_command", "files_from", "files_from_verbatim", "files_from_raw"]
+    )]
+    #[merge(skip)]
+    #[serde(skip)]
+    tar: Option<String>,
+
+    /// Only backup the files and directories listed in this file (can be specified multiple times).
+    /// Empty lines and lines starting with # are ignored. Paths not listed are taken from the parent snapshot,
+    /// listed paths which don't exist are removed.
+    #[clap(long, value_name = "FILE")]
+    #[merge(skip)]
+    #[serde(skip)]
+    files_from: Vec<PathBuf>,
+
+    /// Same as --files-from, but lines are used verbatim
+    #[clap(long, value_name = "FILE")]
+    #[merge(skip)]
+    #[serde(skip)]
+    files_from_verbatim: Vec<PathBuf>,
+
+    /// Same as --files-from, but paths are separated by NUL bytes
+    #[clap(long, value_name = "FILE")]
+    #[merge(skip)]
+    #[serde(skip)]
+    files_from_raw: Vec<PathBuf>,
+
     /// Manually set backup path in snapshot
     #[clap(long, value_name = "PATH")]
     as_path: Option<PathBuf>,
@@ -98,6 +144,10 @@ pub struct Opts {
     #[serde(flatten)]
     ignore_filter_opts: LocalSourceFilterOptions,
 
+    #[clap(flatten, next_help_heading = "Hooks")]
+    #[serde(flatten)]
+    hooks: Hooks,
+
     #[clap(flatten, next_help_heading = "Snapshot options")]
     #[serde(flatten)]
     snap_opts: SnapshotOptions,
@@ -117,6 +167,150 @@ pub struct Opts {
     source: String,
 }
 
+// JSON output if no snapshot was created due to --skip-if-unchanged
+#[derive(Serialize)]
+struct SkippedSnapshot<'a> {
+    snapshot_created: bool,
+    parent: Option<Id>,
+    tree: Id,
+    summary: Option<&'a SnapshotSummary>,
+}
+
+#[derive(Clone, Default, Debug, Parser, Deserialize, Merge)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Hooks {
+    /// Run this command before the backup. If it fails, the source is not backed up
+    #[clap(long, value_name = "COMMAND")]
+    run_before: Option<String>,
+
+    /// Run this command after a successful backup
+    #[clap(long, value_name = "COMMAND")]
+    run_after: Option<String>,
+
+    /// Run this command after the backup, regardless if it was successful
+    #[clap(long, value_name = "COMMAND")]
+    run_finally: Option<String>,
+
+    /// Run this command if the backup or one of the other commands failed
+    #[clap(long, value_name = "COMMAND")]
+    run_on_failure: Option<String>,
+}
+
+impl Hooks {
+    fn call(command: &Option<String>, name: &str, env: &[(&str, String)]) -> Result<()> {
+        if let Some(command) = command {
+            debug!("calling {name} command {command}...");
+            let commands = parse_command::<()>(command)?.1;
+            if commands.is_empty() {
+                bail!("{name} command is empty!");
+            }
+            let status = Command::new(commands[0])
+                .args(&commands[1..])
+                .envs(env.iter().cloned())
+                .status()
+                .with_context(|| format!("failed to call {name} command {command}"))?;
+            if !status.success() {
+                bail!("{name} command {command} was not successful. {status}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Run `backup` together with the hooks. The commands get the source, the status and
+    /// the snapshot id passed in the environment variables `RUSTIC_BACKUP_SOURCE`,
+    /// `RUSTIC_BACKUP_STATUS` and `RUSTIC_SNAPSHOT_ID` (if a snapshot was saved).
+    fn run(
+        &self,
+        source: &str,
+        backup: impl FnOnce() -> Result<SnapshotFile>,
+    ) -> Result<SnapshotFile> {
+        let mut env = vec![("RUSTIC_BACKUP_SOURCE", source.to_string())];
+        let result = Self::call(&self.run_before, "run-before", &env)
+            .and_then(|()| backup())
+            .and_then(|snap| {
+                let mut env = env.clone();
+                env.push(("RUSTIC_BACKUP_STATUS", "success".to_string()));
+                if !snap.id.is_null() {
+                    env.push(("RUSTIC_SNAPSHOT_ID", snap.id.to_hex().to_string()));
+                }
+                Self::call(&self.run_after, "run-after", &env)?;
+                Ok(snap)
+            });
+
+        match &result {
+            Ok(snap) => {
+                env.push(("RUSTIC_BACKUP_STATUS", "success".to_string()));
+                if !snap.id.is_null() {
+                    env.push(("RUSTIC_SNAPSHOT_ID", snap.id.to_hex().to_string()));
+                }
+            }
+            Err(err) => {
+                env.push(("RUSTIC_BACKUP_STATUS", "failure".to_string()));
+                env.push(("RUSTIC_BACKUP_ERROR", err.to_string()));
+                if let Err(err) = Self::call(&self.run_on_failure, "run-on-failure", &env) {
+                    warn!("{err}");
+                }
+            }
+        }
+        let finally = Self::call(&self.run_finally, "run-finally", &env);
+        let snap = result?;
+        finally?;
+        Ok(snap)
+    }
+}
+
+// read the paths given by --files-from, --files-from-verbatim and --files-from-raw
+fn files_from(opts: &Opts) -> Result<Option<Vec<PathBuf>>> {
+    if opts.files_from.is_empty()
+        && opts.files_from_verbatim.is_empty()
+        && opts.files_from_raw.is_empty()
+    {
+        return Ok(None);
+    }
+
+    let mut files = Vec::new();
+    for file in &opts.files_from {
+        for line in std::fs::read_to_string(file)?.lines() {
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                files.push(PathBuf::from(line));
+            }
+        }
+    }
+    for file in &opts.files_from_verbatim {
+        for line in std::fs::read_to_string(file)?.lines() {
+            if !line.is_empty() {
+                files.push(PathBuf::from(line));
+            }
+        }
+    }
+    for file in &opts.files_from_raw {
+        for path in std::fs::read(file)?.split(|c| *c == 0) {
+            if !path.is_empty() {
+                files.push(path_from_bytes(path));
+            }
+        }
+    }
+
+    let dir = std::env::current_dir()?;
+    let files = files
+        .into_iter()
+        .map(|file| Ok(dir.join(file).parse_dot()?.to_path_buf()))
+        .collect::<Result<_>>()?;
+    Ok(Some(files))
+}
+
+#[cfg(not(windows))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::OsStr::from_bytes(bytes).into()
+}
+
+#[cfg(windows)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    String::from_utf8_lossy(bytes).to_string().into()
+}
+
 // Merge backup sources: If a source is already defined on left, use that. Else add it.
 pub fn merge_sources(left: &mut Vec<Opts>, mut right: Vec<Opts>) {
     left.append(&mut right);
@@ -159,8 +353,40 @@ pub(super) fn execute(
         })
         .collect();
 
+    let files = files_from(&opts)?;
+    if matches!(&files, Some(files) if files.is_empty()) {
+        warn!("no files given.");
+        return Ok(());
+    }
     let sources = match (opts.cli_sources.is_empty(), config_opts.is_empty()) {
+        (false, _) if opts.tar.is_some() => bail!("tar cannot be used together with sources!"),
+        (false, _) if files.is_some() => {
+            // the listed files are absolute, so the sources must be absolute, too
+            let dir = std::env::current_dir()?;
+            let paths = PathList::from_strings(&opts.cli_sources, true)?
+                .paths()
+                .into_iter()
+                .map(|path| Ok(dir.join(path).parse_dot()?.to_path_buf()))
+                .collect::<Result<_>>()?;
+            vec![PathList::from_paths(paths)]
+        }
         (false, _) => vec![PathList::from_strings(&opts.cli_sources, true)?],
+        (true, _) if opts.tar.is_some() => vec![PathList::from_strings(&opts.tar, true)?],
+        (true, _) if files.is_some() => {
+            // use the root dir as source, so that the snapshot paths don't depend on the listed files
+            let dir = std::env::current_dir()?;
+            let root = dir
+                .ancestors()
+                .last()
+                .unwrap_or(dir.as_path())
+                .to_path_buf();
+            vec![PathList::from_paths(vec![root])]
+        }
+        (true, _) if opts.stdin_command.is_some() => vec![PathList::from_string("-", false)?],
+        // stdin-command can also be given in the [backup] section of the config file
+        (true, true) if config.backup.stdin_command.is_some() => {
+            vec![PathList::from_string("-", false)?]
+        }
         (true, false) => {
             info!("using all backup sources from config file.");
             config_sources.clone()
@@ -173,6 +399,7 @@ pub(super) fn execute(
 
     let index = IndexBackend::only_full_trees(&repo.dbe, progress_counter(""))?;
 
+    let mut failed = Vec::new();
     for source in sources {
         let mut opts = opts.clone();
         let index = index.clone();
@@ -205,102 +432,183 @@ pub(super) fn execute(
         // merge "backup" section from config file, if given
         opts.merge(config.backup.clone());
 
-        let be = DryRunBackend::new(repo.dbe.clone(), config.global.dry_run);
-        info!("starting to backup {source}...");
-        let as_path = match opts.as_path {
-            None => None,
-            Some(p) => Some(p.parse_dot()?.to_path_buf()),
-        };
-
-        let mut snap = SnapshotFile::new_from_options(opts.snap_opts, time, command.clone())?;
-        match &as_path {
-            Some(p) => snap.paths.set_paths(&[p.to_path_buf()])?,
-            None => snap.paths.set_paths(&backup_path)?,
-        };
+        let hooks = opts.hooks.clone();
+        let backup = || -> Result<SnapshotFile> {
+            let be = DryRunBackend::new(repo.dbe.clone(), config.global.dry_run);
+            info!("starting to backup {source}...");
+            let as_path = match opts.as_path {
+                None => None,
+                Some(p) => Some(p.parse_dot()?.to_path_buf()),
+            };
+
+            let mut snap = SnapshotFile::new_from_options(opts.snap_opts, time, command.clone())?;
+            match &as_path {
+                Some(p) => snap.paths.set_paths(&[p.to_path_buf()])?,
+                None => snap.paths.set_paths(&backup_path)?,
+            };
+
+            // get suitable snapshot group from snapshot and opts.group_by. This is used to filter snapshots for the parent detection
+            let group = SnapshotGroup::from_sn(
+                &snap,
+                &opts.group_by.unwrap_or_else(|| {
+                    SnapshotGroupCriterion::from_str("host,label,paths").unwrap()
+                }),
+            );
 
-        // get suitable snapshot group from snapshot and opts.group_by. This is used to filter snapshots for the parent detection
-        let group = SnapshotGroup::from_sn(
-            &snap,
-            &opts
-                .group_by
-                .unwrap_or_else(|| SnapshotGroupCriterion::from_str("host,label,paths").unwrap()),
-        );
-
-        let parent = match (backup_stdin, opts.force, opts.parent.clone()) {
-            (true, _, _) | (false, true, _) => None,
-            (false, false, None) => {
-                SnapshotFile::latest(&be, |snap| snap.has_group(&group), progress_counter("")).ok()
+            let parent = match (backup_stdin, opts.force, opts.parent.clone()) {
+                (true, _, _) | (false, true, _) => None,
+                (false, false, None) => {
+                    SnapshotFile::latest(&be, |snap| snap.has_group(&group), progress_counter(""))
+                        .ok()
+                }
+                (false, false, Some(parent)) => SnapshotFile::from_id(&be, &parent).ok(),
+            };
+
+            let parent_tree = match &parent {
+                Some(parent) => {
+                    info!("using parent {}", parent.id);
+                    snap.parent = Some(parent.id);
+                    Some(parent.tree)
+                }
+                None => {
+                    info!("using no parent");
+                    None
+                }
+            };
+
+            let mut archiver = Archiver::new(
+                be.clone(),
+                index,
+                &repo.config,
+                parent_tree,
+                opts.ignore_ctime,
+                opts.ignore_inode,
+                snap,
+            )?;
+            let p = progress_bytes("determining size...");
+
+            let mut snap = if opts.tar.is_some() {
+                let path = &backup_path[0];
+                let file = (!backup_stdin).then_some(path.as_path());
+                let src = TarSource::new(file, path)?;
+                archiver.archive(src, path, as_path.as_ref(), &p)?
+            } else if let Some(files) = &files {
+                let src =
+                    FilesSource::new(opts.ignore_save_opts.clone(), files.clone(), &backup_path);
+                // listed paths which don't exist are removed instead of taken from the parent
+                let removed: Vec<_> = src
+                    .removed()
+                    .iter()
+                    .map(|path| match &as_path {
+                        Some(as_path) => as_path.join(path.strip_prefix(&backup_path[0]).unwrap()),
+                        None => path.clone(),
+                    })
+                    .collect();
+                archiver.carry_over_parent(&removed);
+                archiver.archive(src, &backup_path[0], as_path.as_ref(), &p)?
+            } else if backup_stdin {
+                let path = &backup_path[0];
+                match &opts.stdin_command {
+                    None => {
+                        let src = StdinSource::new(path.clone())?;
+                        archiver.archive(src, path, as_path.as_ref(), &p)?
+                    }
+                    Some(command) => {
+                        let (src, mut child) = StdinSource::from_command(path.clone(), command)?;
+                        let mut snap = match archiver.archive(src, path, as_path.as_ref(), &p) {
+                            Ok(snap) => snap,
+                            Err(err) => {
+                                // don't leave the command running or as a zombie
+                                let _ = child.kill();
+                                let _ = child.wait();
+                                return Err(err);
+                            }
+                        };
+                        let status = child.wait()?;
+                        if !status.success() {
+                            bail!(
+                            "stdin-command \"{command}\" failed with {status}, no snapshot saved!"
+                        );
+                        }
+                        let summary = snap.summary.as_mut().unwrap();
+                        summary.stdin_command = Some(command.clone());
+                        summary.stdin_command_exit_code = status.code();
+                        snap
+                    }
+                }
+            } else {
+                let src = LocalSource::new(
+                    opts.ignore_save_opts.clone(),
+                    opts.ignore_filter_opts.clone(),
+                    &backup_path,
+                )?;
+                archiver.archive(src, &backup_path[0], as_path.as_ref(), &p)?
+            };
+            let skip = opts.skip_if_unchanged && parent_tree == Some(snap.tree);
+            if !skip {
+                snap.id = be.save_file(&snap)?;
             }
-            (false, false, Some(parent)) => SnapshotFile::from_id(&be, &parent).ok(),
-        };
 
-        let parent_tree = match &parent {
-            Some(parent) => {
-                info!("using parent {}", parent.id);
-                snap.parent = Some(parent.id);
-                Some(parent.tree)
-            }
-            None => {
-                info!("using no parent");
-                None
+            if opts.json {
+                let mut stdout = std::io::stdout();
+                if skip {
+                    let skipped = SkippedSnapshot {
+                        snapshot_created: false,
+                        parent: snap.parent,
+                        tree: snap.tree,
+                        summary: snap.summary.as_ref(),
+                    };
+                    serde_json::to_writer_pretty(&mut stdout, &skipped)?;
+                } else {
+                    serde_json::to_writer_pretty(&mut stdout, &snap)?;
+                }
+            } else {
+                let summary = snap.summary.as_ref().unwrap();
+                println!(
+                    "Files:       {} new, {} changed, {} unchanged",
+                    summary.files_new, summary.files_changed, summary.files_unmodified
+                );
+                println!(
+                    "Dirs:        {} new, {} changed, {} unchanged",
+                    summary.dirs_new, summary.dirs_changed, summary.dirs_unmodified
+                );
+                debug!("Data Blobs:  {} new", summary.data_blobs);
+                debug!("Tree Blobs:  {} new", summary.tree_blobs);
+                println!(
+                    "Added to the repo: {} (raw: {})",
+                    bytes(summary.data_added_packed),
+                    bytes(summary.data_added)
+                );
+
+                println!(
+                    "processed {} files, {}",
+                    summary.total_files_processed,
+                    bytes(summary.total_bytes_processed)
+                );
+                if skip {
+                    println!(
+                        "nothing changed compared to parent snapshot {}, no snapshot saved.",
+                        snap.parent.unwrap()
+                    );
+                } else {
+                    println!("snapshot {} successfully saved.", snap.id);
+                }
             }
-        };
 
-        let archiver = Archiver::new(
-            be,
-            index,
-            &repo.config,
-            parent_tree,
-            opts.ignore_ctime,
-            opts.ignore_inode,
-            snap,
-        )?;
-        let p = progress_bytes("determining size...");
-
-        let snap = if backup_stdin {
-            let path = &backup_path[0];
-            let src = StdinSource::new(path.clone())?;
-            archiver.archive(src, path, as_path.as_ref(), &p)?
-        } else {
-            let src = LocalSource::new(
-                opts.ignore_save_opts.clone(),
-                opts.ignore_filter_opts.clone(),
-                &backup_path,
-            )?;
-            archiver.archive(src, &backup_path[0], as_path.as_ref(), &p)?
+            Ok(snap)
         };
-
-        if opts.json {
-            let mut stdout = std::io::stdout();
-            serde_json::to_writer_pretty(&mut stdout, &snap)?;
-        } else {
-            let summary = snap.summary.unwrap();
-            println!(
-                "Files:       {} new, {} changed, {} unchanged",
-                summary.files_new, summary.files_changed, summary.files_unmodified
-            );
-            println!(
-                "Dirs:        {} new, {} changed, {} unchanged",
-                summary.dirs_new, summary.dirs_changed, summary.dirs_unmodified
-            );
-            debug!("Data Blobs:  {} new", summary.data_blobs);
-            debug!("Tree Blobs:  {} new", summary.tree_blobs);
-            println!(
-                "Added to the repo: {} (raw: {})",
-                bytes(summary.data_added_packed),
-                bytes(summary.data_added)
-            );
-
-            println!(
-                "processed {} files, {}",
-                summary.total_files_processed,
-                bytes(summary.total_bytes_processed)
-            );
-            println!("snapshot {} successfully saved.", snap.id);
+        // a failing source doesn't prevent the backup of the other sources
+        match hooks.run(&source.to_string(), backup) {
+            Ok(_) => info!("backup of {source} done."),
+            Err(err) => {
+                error!("backup of {source} failed: {err:?}");
+                failed.push(source.to_string());
+            }
         }
-
-        info!("backup of {source} done.");
     }
 
+    if !failed.is_empty() {
+        bail!("backup of {} failed!", failed.join(", "));
+    }
     Ok(())
 }
@@ -0,0 +1,63 @@
+use std::time::SystemTime;
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use clap::Parser;
+
+use crate::backend::{base_dir, CacheDir};
+
+use super::{bytes, table_right_from, Config};
+
+#[derive(Parser)]
+pub(super) struct Opts {
+    /// Remove caches which have not been used for longer than --cache-max-age
+    #[clap(long)]
+    cleanup: bool,
+}
+
+pub(super) fn execute(config: Config, opts: Opts) -> Result<()> {
+    let base = base_dir(config.repository.cache_dir.clone())?;
+    let max_age = config.repository.cache_max_age();
+    let now = SystemTime::now();
+
+    let mut dirs = CacheDir::list(&base)?;
+    dirs.sort_unstable_by_key(|dir| dir.last_used);
+    let mut table = table_right_from(1, ["Repository cache", "Size", "Last used", "Old"]);
+    let mut old = Vec::new();
+    for dir in dirs {
+        let is_old = now.duration_since(dir.last_used).unwrap_or_default() > max_age;
+        let last_used: DateTime<Local> = dir.last_used.into();
+        let name = dir.path.file_name().unwrap_or_default().to_string_lossy();
+        table.add_row([
+            name.to_string(),
+            bytes(dir.size()),
+            last_used.format("%Y-%m-%d %H:%M:%S").to_string(),
+            if is_old { "yes" } else { "" }.to_string(),
+        ]);
+        if is_old {
+            old.push(dir);
+        }
+    }
+    println!("caches in {base:?}:");
+    println!("{table}");
+
+    if opts.cleanup {
+        for dir in &old {
+            match config.global.dry_run {
+                true => println!("would remove cache {:?}", dir.path),
+                false => {
+                    dir.remove()?;
+                    println!("removed cache {:?}", dir.path);
+                }
+            }
+        }
+    } else if !old.is_empty() {
+        println!(
+            "{} caches are older than {}. Use --cleanup to remove them.",
+            old.len(),
+            humantime::format_duration(max_age)
+        );
+    }
+
+    Ok(())
+}
@@ -1,15 +1,18 @@
 use std::collections::HashMap;
+use std::str::FromStr;
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use bytes::Bytes;
+use bytesize::ByteSize;
 use clap::Parser;
 use indicatif::ProgressBar;
 use itertools::Itertools;
 use log::*;
+use rand::prelude::*;
 use rayon::prelude::*;
 use zstd::stream::decode_all;
 
-use super::{progress_bytes, progress_counter};
+use super::{bytes, progress_bytes, progress_counter};
 use crate::backend::{Cache, DecryptReadBackend, FileType, ReadBackend};
 use crate::blob::{BlobType, NodeType, TreeStreamerOnce};
 use crate::commands::helpers::progress_spinner;
@@ -30,6 +33,74 @@ pub(super) struct Opts {
     /// Read all data blobs
     #[clap(long)]
     read_data: bool,
+
+    /// Read only a subset of the data packs. Use n/m to select the n-th of m groups of packs
+    /// (which are deterministically chosen by the pack id), x% or a size like 100G to select
+    /// randomly chosen packs up to the given part of the total pack size. Implies --read-data
+    #[clap(long, value_name = "SUBSET")]
+    read_data_subset: Option<ReadSubsetOption>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ReadSubsetOption {
+    Percentage(f64),
+    Size(ByteSize),
+    IdSubSet(u32, u32),
+}
+
+impl FromStr for ReadSubsetOption {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let result = if let Some(p) = s.strip_suffix('%') {
+            let p: f64 = p.parse()?;
+            if !(0.0..=100.0).contains(&p) {
+                bail!("percentage must be between 0% and 100%");
+            }
+            Self::Percentage(p / 100.0)
+        } else if let Some((n, m)) = s.split_once('/') {
+            let (n, m) = (n.parse()?, m.parse()?);
+            if n == 0 || n > m {
+                bail!("n/m must satisfy 1 <= n <= m");
+            }
+            Self::IdSubSet(n, m)
+        } else {
+            Self::Size(ByteSize::from_str(s).map_err(|err| anyhow!(err))?)
+        };
+        Ok(result)
+    }
+}
+
+impl ReadSubsetOption {
+    /// Select the packs to read from the given packs
+    fn select(self, mut packs: Vec<IndexPack>) -> Vec<IndexPack> {
+        let max_size = match self {
+            Self::IdSubSet(n, m) => {
+                packs.retain(|pack| id_bucket(&pack.id, m) == n - 1);
+                return packs;
+            }
+            Self::Percentage(p) => {
+                let total: u64 = packs.iter().map(|pack| u64::from(pack.pack_size())).sum();
+                (total as f64 * p) as u64
+            }
+            Self::Size(size) => size.as_u64(),
+        };
+
+        packs.shuffle(&mut thread_rng());
+        let mut size = 0;
+        packs
+            .into_iter()
+            .take_while(|pack| {
+                size += u64::from(pack.pack_size());
+                size <= max_size
+            })
+            .collect()
+    }
+}
+
+/// Deterministically map the id into one of `m` buckets
+fn id_bucket(id: &Id, m: u32) -> u32 {
+    let first = u32::from_str_radix(&id.to_hex()[0..8], 16).unwrap();
+    first % m
 }
 
 pub(super) fn execute(repo: OpenRepository, opts: Opts) -> Result<()> {
@@ -59,7 +130,27 @@ pub(super) fn execute(repo: OpenRepository, opts: Opts) -> Result<()> {
         }
     }
 
-    let index_collector = check_packs(be, hot_be, opts.read_data)?;
+    for file in raw_be.list_orphaned()? {
+        warn!("orphaned temporary file {file:?} from an interrupted write. You can remove it.");
+    }
+
+    for file_type in [
+        FileType::Config,
+        FileType::Key,
+        FileType::Snapshot,
+        FileType::Index,
+        FileType::Pack,
+    ] {
+        for divergence in raw_be.list_divergence(file_type)? {
+            error!("{divergence}");
+        }
+    }
+
+    let index_collector = check_packs(
+        be,
+        hot_be,
+        opts.read_data || opts.read_data_subset.is_some(),
+    )?;
 
     if let Some(cache) = &cache {
         let p = progress_spinner("cleaning up packs from cache...");
@@ -73,33 +164,38 @@ pub(super) fn execute(repo: OpenRepository, opts: Opts) -> Result<()> {
         }
     }
 
-    let total_pack_size: u64 = index_collector
-        .data_packs()
-        .iter()
-        .map(|(_, size)| u64::from(*size))
-        .sum::<u64>()
-        + index_collector
-            .tree_packs()
-            .iter()
-            .map(|(_, size)| u64::from(*size))
-            .sum::<u64>();
-
     let index_be = IndexBackend::new_from_index(be, index_collector.into_index());
 
     check_snapshots(&index_be)?;
 
-    if opts.read_data {
+    if opts.read_data || opts.read_data_subset.is_some() {
+        let packs: Vec<_> = index_be.into_index().into_iter().collect();
+        let total_packs = packs.len();
+        let packs = match opts.read_data_subset {
+            Some(subset) => subset.select(packs),
+            None => packs,
+        };
+        let total_pack_size = packs.iter().map(|pack| u64::from(pack.pack_size())).sum();
+        // when reading a subset, report which packs have been verified
+        let report = opts.read_data_subset.is_some();
+        if report {
+            info!(
+                "reading subset of {} of {total_packs} packs ({})",
+                packs.len(),
+                bytes(total_pack_size)
+            );
+        }
+
         let p = progress_bytes("reading pack data...");
         p.set_length(total_pack_size);
 
-        index_be
-            .into_index()
-            .into_iter()
-            .par_bridge()
+        packs
+            .into_par_iter()
             .for_each_with((be.clone(), p.clone()), |(be, p), pack| {
                 let id = pack.id;
                 let data = be.read_full(FileType::Pack, &id).unwrap();
                 match check_pack(be, pack, data, p) {
+                    Ok(()) if report => info!("pack {id:?}: data is ok"),
                     Ok(()) => {}
                     Err(err) => error!("Error reading pack {id} : {err}",),
                 }
@@ -288,7 +384,7 @@ fn check_snapshots(index: &impl IndexedBackend) -> Result<()> {
     let p = progress_counter("checking trees...");
     let mut tree_streamer = TreeStreamerOnce::new(index.clone(), snap_trees, p)?;
     while let Some(item) = tree_streamer.next().transpose()? {
-        let (path, tree) = item;
+        let (path, tree, _) = item;
         for node in tree.nodes {
             match node.node_type {
                 NodeType::File => match &node.content {
@@ -112,7 +112,7 @@ fn copy(
     tree_streamer
         .par_bridge()
         .try_for_each(|item| -> Result<_> {
-            let (_, tree) = item?;
+            let (_, tree, _) = item?;
             tree.nodes.par_iter().try_for_each(|node| {
                 match node.node_type {
                     NodeType::File => {
@@ -1,9 +1,16 @@
-use anyhow::{bail, Result};
-use clap::Parser;
-use std::io::Write;
-use std::path::Path;
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
 
-use crate::blob::{BlobType, NodeType, Tree};
+use anyhow::{anyhow, bail, Result};
+use bytes::{Buf, Bytes};
+use chrono::{Datelike, Timelike};
+use clap::{Parser, ValueEnum};
+use log::*;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::blob::{BlobType, Node, NodeStreamer, NodeType, Tree};
+use crate::id::Id;
 use crate::index::{IndexBackend, IndexedBackend};
 use crate::repofile::SnapshotFile;
 use crate::repository::OpenRepository;
@@ -12,9 +19,20 @@ use super::{progress_counter, Config};
 
 #[derive(Parser)]
 pub(super) struct Opts {
-    /// file from snapshot to dump
+    /// file or dir from snapshot to dump
     #[clap(value_name = "SNAPSHOT[:PATH]")]
     snap: String,
+
+    /// Dump as archive of the given format. This is needed to dump dirs.
+    /// Paths within the archive are relative to PATH.
+    #[clap(long, value_enum, value_name = "FORMAT")]
+    archive: Option<ArchiveFormat>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ArchiveFormat {
+    Tar,
+    Zip,
 }
 
 pub(super) fn execute(repo: OpenRepository, config: Config, opts: Opts) -> Result<()> {
@@ -30,16 +48,189 @@ pub(super) fn execute(repo: OpenRepository, config: Config, opts: Opts) -> Resul
     let index = IndexBackend::new(be, progress_counter(""))?;
     let node = Tree::node_from_path(&index, snap.tree, Path::new(path))?;
 
-    if node.node_type != NodeType::File {
-        bail!("dump only supports regular files!");
+    let mut stdout = io::stdout();
+    match opts.archive {
+        None => {
+            if node.node_type != NodeType::File {
+                bail!("dump only supports regular files! Use --archive to dump dirs.");
+            }
+            io::copy(&mut ContentReader::new(&index, &node), &mut stdout)?;
+        }
+        Some(ArchiveFormat::Tar) => dump_tar(&index, &node, &mut stdout)?,
+        Some(ArchiveFormat::Zip) => {
+            // zip needs to seek in its output, so first write to a temp file
+            let file = dump_zip(&index, &node, tempfile::tempfile()?)?;
+            io::copy(&mut io::BufReader::new(file), &mut stdout)?;
+        }
     }
 
-    let mut stdout = std::io::stdout();
-    for id in node.content.unwrap() {
-        // TODO: cache blobs which are needed later
-        let data = index.blob_from_backend(BlobType::Data, &id)?;
-        stdout.write_all(&data)?;
-    }
+    Ok(())
+}
+
+type NodeIter = Box<dyn Iterator<Item = Result<(PathBuf, Node)>>>;
 
+/// Iterate over the node itself (if it is no dir) or over all nodes below it
+fn nodes(index: &impl IndexedBackend, node: &Node) -> Result<NodeIter> {
+    Ok(match node.is_dir() {
+        true => Box::new(NodeStreamer::new(index.clone(), node)?),
+        false => Box::new(std::iter::once(Ok((
+            PathBuf::from(node.name()),
+            node.clone(),
+        )))),
+    })
+}
+
+fn dump_tar(index: &impl IndexedBackend, node: &Node, w: impl Write) -> Result<()> {
+    let mut ar = tar::Builder::new(w);
+    for item in nodes(index, node)? {
+        let (path, node) = item?;
+        let meta = &node.meta;
+
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(unix_mode(&node));
+        header.set_mtime(
+            meta.mtime
+                .map_or(0, |t| u64::try_from(t.timestamp()).unwrap_or_default()),
+        );
+        header.set_uid(meta.uid.unwrap_or_default().into());
+        header.set_gid(meta.gid.unwrap_or_default().into());
+        if let Some(user) = &meta.user {
+            header.set_username(user)?;
+        }
+        if let Some(group) = &meta.group {
+            header.set_groupname(group)?;
+        }
+        header.set_size(0);
+
+        match &node.node_type {
+            NodeType::File => {
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_size(meta.size);
+                ar.append_data(&mut header, &path, ContentReader::new(index, &node))?;
+            }
+            NodeType::Dir => {
+                header.set_entry_type(tar::EntryType::Directory);
+                ar.append_data(&mut header, &path, io::empty())?;
+            }
+            NodeType::Symlink { linktarget } => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                ar.append_link(&mut header, &path, linktarget)?;
+            }
+            NodeType::Dev { device } | NodeType::Chardev { device } => {
+                header.set_entry_type(match node.node_type {
+                    NodeType::Dev { .. } => tar::EntryType::Block,
+                    _ => tar::EntryType::Char,
+                });
+                header.set_device_major(major(*device))?;
+                header.set_device_minor(minor(*device))?;
+                ar.append_data(&mut header, &path, io::empty())?;
+            }
+            NodeType::Fifo => {
+                header.set_entry_type(tar::EntryType::Fifo);
+                ar.append_data(&mut header, &path, io::empty())?;
+            }
+            NodeType::Socket => warn!("{path:?}: sockets cannot be stored in tar, skipping."),
+        }
+    }
+    ar.finish()?;
     Ok(())
 }
+
+fn dump_zip<W: Write + Seek>(index: &impl IndexedBackend, node: &Node, w: W) -> Result<W> {
+    let mut zip = ZipWriter::new(w);
+    for item in nodes(index, node)? {
+        let (path, node) = item?;
+        let name = path
+            .to_str()
+            .ok_or_else(|| anyhow!("{path:?} is no valid unicode, which is needed for zip"))?;
+
+        let mut options = FileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .unix_permissions(unix_mode(&node))
+            .large_file(node.meta.size >= u64::from(u32::MAX));
+        if let Some(mtime) = node.meta.mtime {
+            // zip only supports the years 1980 to 2107; other times keep the default time
+            if let Ok(mtime) = zip::DateTime::from_date_and_time(
+                u16::try_from(mtime.year()).unwrap_or_default(),
+                u8::try_from(mtime.month())?,
+                u8::try_from(mtime.day())?,
+                u8::try_from(mtime.hour())?,
+                u8::try_from(mtime.minute())?,
+                u8::try_from(mtime.second())?,
+            ) {
+                options = options.last_modified_time(mtime);
+            }
+        }
+
+        match &node.node_type {
+            NodeType::File => {
+                zip.start_file(name, options)?;
+                io::copy(&mut ContentReader::new(index, &node), &mut zip)?;
+            }
+            NodeType::Dir => zip.add_directory(name, options)?,
+            NodeType::Symlink { linktarget } => zip.add_symlink(name, linktarget, options)?,
+            _ => warn!("{path:?}: special files cannot be stored in zip, skipping."),
+        }
+    }
+    let mut w = zip.finish()?;
+    w.rewind()?;
+    Ok(w)
+}
+
+/// Unix permission bits of the node including setuid, setgid and sticky bits
+fn unix_mode(node: &Node) -> u32 {
+    let default = match node.is_dir() {
+        true => 0o755,
+        false => 0o644,
+    };
+    #[cfg(not(windows))]
+    let mode = node.meta.mode.map(crate::backend::mapper::map_mode_from_go);
+    #[cfg(windows)]
+    let mode = node.meta.mode;
+    mode.map_or(default, |mode| mode & 0o7777)
+}
+
+// device numbers are decoded like glibc does, see makedev(3)
+fn major(device: u64) -> u32 {
+    (((device >> 32) & 0xffff_f000) | ((device >> 8) & 0x0000_0fff)) as u32
+}
+
+fn minor(device: u64) -> u32 {
+    (((device >> 12) & 0xffff_ff00) | (device & 0x0000_00ff)) as u32
+}
+
+/// [`ContentReader`] reads the contents of a file node blob by blob
+struct ContentReader<'a, I: IndexedBackend> {
+    index: &'a I,
+    blobs: std::vec::IntoIter<Id>,
+    current: Bytes,
+}
+
+impl<'a, I: IndexedBackend> ContentReader<'a, I> {
+    fn new(index: &'a I, node: &Node) -> Self {
+        Self {
+            index,
+            blobs: node.content.clone().unwrap_or_default().into_iter(),
+            current: Bytes::new(),
+        }
+    }
+}
+
+impl<'a, I: IndexedBackend> Read for ContentReader<'a, I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while !self.current.has_remaining() {
+            match self.blobs.next() {
+                None => return Ok(0),
+                Some(id) => {
+                    self.current = self
+                        .index
+                        .blob_from_backend(BlobType::Data, &id)
+                        .map_err(io::Error::other)?;
+                }
+            }
+        }
+        let len = buf.len().min(self.current.len());
+        self.current.copy_to_slice(&mut buf[..len]);
+        Ok(len)
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use clap::Parser;
+use globset::{GlobBuilder, GlobMatcher};
+use indicatif::ProgressBar;
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+
+use super::{bytes, progress_counter, table_right_from, Config};
+use crate::blob::{Metadata, Node, NodeType, Tree};
+use crate::id::Id;
+use crate::index::{IndexBackend, IndexedBackend};
+use crate::repofile::SnapshotFile;
+use crate::repository::OpenRepository;
+
+#[derive(Parser)]
+pub(super) struct Opts {
+    /// Glob pattern to search for. If the pattern contains no '/', it is matched against
+    /// the file name, else against the full path within the snapshot
+    #[clap(
+        value_name = "PATTERN",
+        required_unless_present_any = &["tree", "blob"],
+        conflicts_with_all = &["tree", "blob"]
+    )]
+    pattern: Option<String>,
+
+    /// Interpret PATTERN as regular expression which is matched against the full path
+    #[clap(long)]
+    regex: bool,
+
+    /// Ignore the casing of file names when matching PATTERN
+    #[clap(long, short = 'i')]
+    ignore_case: bool,
+
+    /// Find trees with the given id (can be specified multiple times)
+    #[clap(long, value_name = "ID")]
+    tree: Vec<String>,
+
+    /// Find files containing the data blob with the given id (can be specified multiple times)
+    #[clap(long, value_name = "ID")]
+    blob: Vec<String>,
+
+    /// Snapshots to search in. If none is given, use filter options to filter from all snapshots
+    #[clap(long, value_name = "ID")]
+    snapshot: Vec<String>,
+
+    /// Show matches in json format
+    #[clap(long)]
+    json: bool,
+}
+
+enum Matcher {
+    Glob {
+        glob: GlobMatcher,
+        name_only: bool,
+    },
+    Regex(Regex),
+    Ids {
+        trees: HashSet<Id>,
+        blobs: HashSet<Id>,
+    },
+}
+
+impl Matcher {
+    fn from_opts(opts: &Opts) -> Result<Self> {
+        Ok(match &opts.pattern {
+            Some(pattern) if opts.regex => Self::Regex(
+                RegexBuilder::new(pattern)
+                    .case_insensitive(opts.ignore_case)
+                    .build()?,
+            ),
+            Some(pattern) => Self::Glob {
+                glob: GlobBuilder::new(pattern)
+                    .case_insensitive(opts.ignore_case)
+                    .literal_separator(true)
+                    .build()?
+                    .compile_matcher(),
+                name_only: !pattern.contains('/'),
+            },
+            None => {
+                let parse = |ids: &[String]| -> Result<HashSet<Id>> {
+                    ids.iter().map(|id| Ok(Id::from_hex(id)?)).collect()
+                };
+                Self::Ids {
+                    trees: parse(&opts.tree)?,
+                    blobs: parse(&opts.blob)?,
+                }
+            }
+        })
+    }
+
+    /// Returns whether the match result only depends on the node itself and not on its path.
+    /// In this case, the matches within a tree are the same wherever the tree is located.
+    fn is_path_independent(&self) -> bool {
+        matches!(
+            self,
+            Self::Ids { .. }
+                | Self::Glob {
+                    name_only: true,
+                    ..
+                }
+        )
+    }
+
+    fn matches(&self, path: &Path, node: &Node) -> bool {
+        match self {
+            Self::Glob {
+                glob,
+                name_only: true,
+            } => glob.is_match(node.name()),
+            Self::Glob {
+                glob,
+                name_only: false,
+            } => glob.is_match(path),
+            Self::Regex(regex) => regex.is_match(&path.to_string_lossy()),
+            Self::Ids { trees, blobs } => {
+                node.subtree.is_some_and(|id| trees.contains(&id))
+                    || node.content.iter().flatten().any(|id| blobs.contains(id))
+            }
+        }
+    }
+
+    fn needs_content(&self) -> bool {
+        matches!(self, Self::Ids { blobs, .. } if !blobs.is_empty())
+    }
+}
+
+#[derive(Serialize)]
+struct FindMatch {
+    snapshot: Id,
+    time: DateTime<Local>,
+    path: PathBuf,
+    node: Node,
+}
+
+pub(super) fn execute(repo: OpenRepository, config: Config, opts: Opts) -> Result<()> {
+    let be = &repo.dbe;
+    let matcher = Matcher::from_opts(&opts)?;
+
+    let mut snapshots = match opts.snapshot.is_empty() {
+        true => SnapshotFile::all_from_backend(be, &config.snapshot_filter)?,
+        false => SnapshotFile::from_ids(be, &opts.snapshot)?,
+    };
+    snapshots.sort_unstable();
+
+    let index = IndexBackend::only_full_trees(be, progress_counter(""))?;
+    let mut finder = Finder {
+        index: &index,
+        matcher: &matcher,
+        matches: HashMap::new(),
+        p: progress_counter("searching trees..."),
+    };
+
+    let mut matches = Vec::new();
+    for sn in &snapshots {
+        let mut found = Vec::new();
+        let mut path = PathBuf::from("/");
+        if let Matcher::Ids { trees: ids, .. } = &matcher {
+            if ids.contains(&sn.tree) {
+                let mut root = Node::new_node(OsStr::new(""), NodeType::Dir, Metadata::default());
+                root.subtree = Some(sn.tree);
+                found.push((path.clone(), root));
+            }
+        }
+        let key = finder.search(sn.tree, &mut path)?;
+        finder.expand(&key, &mut path, &mut found);
+        matches.extend(found.into_iter().map(|(path, node)| FindMatch {
+            snapshot: sn.id,
+            time: sn.time,
+            path,
+            node,
+        }));
+    }
+    finder.p.finish_and_clear();
+
+    if opts.json {
+        let mut stdout = std::io::stdout();
+        serde_json::to_writer_pretty(&mut stdout, &matches)?;
+        return Ok(());
+    }
+
+    let snapshot_count = matches
+        .iter()
+        .map(|m| m.snapshot)
+        .collect::<HashSet<_>>()
+        .len();
+    let count = matches.len();
+
+    let mut table = table_right_from(3, ["Snapshot", "Path", "Modified", "Size"]);
+    for m in matches {
+        let mtime = m.node.meta.mtime.map_or_else(
+            || "?".to_string(),
+            |t| t.format("%Y-%m-%d %H:%M:%S").to_string(),
+        );
+        let size = match m.node.is_file() {
+            true => bytes(m.node.meta.size),
+            false => String::new(),
+        };
+        table.add_row([
+            m.snapshot.to_string(),
+            m.path.display().to_string(),
+            mtime,
+            size,
+        ]);
+    }
+    if count > 0 {
+        println!("{table}");
+    }
+    println!("found {count} match(es) in {snapshot_count} snapshot(s)");
+
+    Ok(())
+}
+
+/// A searched tree is identified by its id and, if the matcher depends on the path, its path
+type TreeKey = (Id, Option<PathBuf>);
+
+enum TreeMatch {
+    /// A matching node within the tree
+    Node(Box<Node>),
+    /// A subtree with the given name which contains matches
+    Subtree(OsString, TreeKey),
+}
+
+/// [`Finder`] searches trees and keeps the matches found within every tree, so that trees contained
+/// in several snapshots (or several times within a snapshot) are only read and searched once.
+struct Finder<'a, I: IndexedBackend> {
+    index: &'a I,
+    matcher: &'a Matcher,
+    matches: HashMap<TreeKey, Vec<TreeMatch>>,
+    p: ProgressBar,
+}
+
+impl<'a, I: IndexedBackend> Finder<'a, I> {
+    /// Recursively search the tree `id` located at `path` and return its key in `matches`
+    fn search(&mut self, id: Id, path: &mut PathBuf) -> Result<TreeKey> {
+        let key = (
+            id,
+            (!self.matcher.is_path_independent()).then(|| path.clone()),
+        );
+        if self.matches.contains_key(&key) {
+            return Ok(key);
+        }
+
+        let tree = Tree::from_backend(self.index, id)?;
+        self.p.inc(1);
+        let mut found = Vec::new();
+        for mut node in tree.nodes {
+            if !self.matcher.needs_content() {
+                node.content = None;
+            }
+            node.meta.extended_attributes = Vec::new();
+            let name = node.name();
+            let subtree = node.subtree;
+
+            path.push(&name);
+            if self.matcher.matches(path, &node) {
+                found.push(TreeMatch::Node(Box::new(node)));
+            }
+            if let Some(subtree) = subtree {
+                let subtree_key = self.search(subtree, path)?;
+                if !self.matches[&subtree_key].is_empty() {
+                    found.push(TreeMatch::Subtree(name, subtree_key));
+                }
+            }
+            path.pop();
+        }
+
+        self.matches.insert(key.clone(), found);
+        Ok(key)
+    }
+
+    /// Add all matches within the searched tree `key` located at `path` to `found`
+    fn expand(&self, key: &TreeKey, path: &mut PathBuf, found: &mut Vec<(PathBuf, Node)>) {
+        for tree_match in &self.matches[key] {
+            match tree_match {
+                TreeMatch::Node(node) => {
+                    found.push((path.join(node.name()), node.as_ref().clone()));
+                }
+                TreeMatch::Subtree(name, key) => {
+                    path.push(name);
+                    self.expand(key, path, found);
+                    path.pop();
+                }
+            }
+        }
+    }
+}
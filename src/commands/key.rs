@@ -1,12 +1,14 @@
 use std::fs::File;
 use std::io::BufReader;
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use clap::{Parser, Subcommand};
 use rpassword::{prompt_password, read_password_from_bufread};
 
-use crate::backend::{FileType, WriteBackend};
+use super::table_with_titles;
+use crate::backend::{FileType, ReadBackend, WriteBackend};
 use crate::crypto::{hash, Key};
+use crate::id::Id;
 use crate::repofile::KeyFile;
 use crate::repository::OpenRepository;
 
@@ -20,6 +22,15 @@ pub(super) struct Opts {
 enum Command {
     /// Add a new key to the repository
     Add(AddOpts),
+
+    /// List all keys of the repository; the key currently in use is marked with '*'
+    List,
+
+    /// Remove a key from the repository
+    Remove(RemoveOpts),
+
+    /// Change the password by adding a new key and removing the key currently in use
+    Passwd(AddOpts),
 }
 
 #[derive(Parser)]
@@ -32,6 +43,13 @@ pub(crate) struct AddOpts {
     pub key_opts: KeyOpts,
 }
 
+#[derive(Parser)]
+pub(crate) struct RemoveOpts {
+    /// Id of the key to remove
+    #[clap(value_name = "ID")]
+    id: String,
+}
+
 #[derive(Parser)]
 pub(crate) struct KeyOpts {
     /// Set 'hostname' in public key information
@@ -49,11 +67,14 @@ pub(crate) struct KeyOpts {
 
 pub(super) fn execute(repo: OpenRepository, opts: Opts) -> Result<()> {
     match opts.command {
-        Command::Add(opt) => add_key(&repo.dbe, repo.key, opt),
+        Command::Add(opt) => add_key(&repo.dbe, repo.key, opt).map(|_| ()),
+        Command::List => list_keys(&repo.dbe, &repo.key_id),
+        Command::Remove(opt) => remove_key(&repo.dbe, &repo.key_id, opt),
+        Command::Passwd(opt) => change_password(&repo.dbe, repo.key, &repo.key_id, opt),
     }
 }
 
-fn add_key(be: &impl WriteBackend, key: Key, opts: AddOpts) -> Result<()> {
+fn add_key(be: &impl WriteBackend, key: Key, opts: AddOpts) -> Result<Id> {
     let pass = match opts.new_password_file {
         Some(file) => {
             let mut file = BufReader::new(File::open(file)?);
@@ -68,5 +89,60 @@ fn add_key(be: &impl WriteBackend, key: Key, opts: AddOpts) -> Result<()> {
     be.write_bytes(FileType::Key, &id, false, data.into())?;
 
     println!("key {id} successfully added.");
+    Ok(id)
+}
+
+fn list_keys(be: &impl ReadBackend, key_id: &Id) -> Result<()> {
+    let mut keys = be
+        .list(FileType::Key)?
+        .into_iter()
+        .map(|id| Ok((id, KeyFile::from_backend(be, &id)?)))
+        .collect::<Result<Vec<_>>>()?;
+    keys.sort_unstable_by_key(|(_, key)| key.created);
+
+    let mut table = table_with_titles(["ID", "User", "Host", "Created"]);
+    for (id, key) in keys {
+        let mark = if &id == key_id { '*' } else { ' ' };
+        table.add_row([
+            format!("{mark}{id}"),
+            key.username.unwrap_or_default(),
+            key.hostname.unwrap_or_default(),
+            key.created
+                .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_default(),
+        ]);
+    }
+    println!("{table}");
+    Ok(())
+}
+
+fn remove_key(be: &impl WriteBackend, key_id: &Id, opts: RemoveOpts) -> Result<()> {
+    let id = be.find_id(FileType::Key, &opts.id)?;
+    if &id == key_id {
+        bail!(
+            "key {id} is currently in use and cannot be removed. Use `key passwd` to replace it."
+        );
+    }
+    if be.list(FileType::Key)?.len() <= 1 {
+        bail!("key {id} is the last key of the repository and cannot be removed.");
+    }
+
+    be.remove(FileType::Key, &id, false)?;
+    println!("key {id} successfully removed.");
+    Ok(())
+}
+
+fn change_password(be: &impl WriteBackend, key: Key, key_id: &Id, mut opts: AddOpts) -> Result<()> {
+    // keep the information from the old key unless explicitly given
+    let old_key = KeyFile::from_backend(be, key_id)?;
+    let ko = &mut opts.key_opts;
+    ko.hostname = ko.hostname.take().or(old_key.hostname);
+    ko.username = ko.username.take().or(old_key.username);
+
+    let new_id = add_key(be, key, opts)?;
+    be.remove(FileType::Key, key_id, false).with_context(|| {
+        format!("new key {new_id} was added, but the old key {key_id} could not be removed")
+    })?;
+    println!("key {key_id} successfully removed.");
     Ok(())
 }
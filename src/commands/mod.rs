@@ -3,17 +3,19 @@ use std::path::PathBuf;
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
+use log::*;
 use merge::Merge;
 use serde::Deserialize;
 use serde_with::{serde_as, DisplayFromStr};
 use simplelog::{ColorChoice, CombinedLogger, LevelFilter, TermLogger, TerminalMode, WriteLogger};
 
-use crate::backend::{FileType, ReadBackend};
+use crate::backend::{retry_count, FileType, ReadBackend};
 use crate::repository::Repository;
 
 use helpers::*;
 
 mod backup;
+mod cache;
 mod cat;
 mod check;
 mod completions;
@@ -22,6 +24,7 @@ mod configfile;
 mod copy;
 mod diff;
 mod dump;
+mod find;
 mod forget;
 mod helpers;
 mod init;
@@ -29,12 +32,16 @@ mod key;
 mod list;
 mod ls;
 mod merge_cmd;
+#[cfg(not(windows))]
+mod mount;
 mod prune;
 mod repair;
 mod repoinfo;
 mod restore;
+mod rewrite;
 mod self_update;
 mod snapshots;
+mod stats;
 mod tag;
 
 use configfile::Config;
@@ -102,6 +109,9 @@ enum Command {
     /// Backup to the repository
     Backup(backup::Opts),
 
+    /// List the caches of all repositories and remove old ones
+    Cache(cache::Opts),
+
     /// Show raw data of repository files and blobs
     Cat(cat::Opts),
 
@@ -124,6 +134,9 @@ enum Command {
     /// dump the contents of a file in a snapshot to stdout
     Dump(dump::Opts),
 
+    /// Find files, trees or blobs within snapshots
+    Find(find::Opts),
+
     /// Remove snapshots from the repository
     Forget(forget::Opts),
 
@@ -142,6 +155,10 @@ enum Command {
     /// Merge snapshots
     Merge(merge_cmd::Opts),
 
+    /// Mount the repository as read-only FUSE filesystem
+    #[cfg(not(windows))]
+    Mount(mount::Opts),
+
     /// Show a detailed overview of the snapshots within the repository
     Snapshots(snapshots::Opts),
 
@@ -160,9 +177,15 @@ enum Command {
     /// Repair a snapshot/path
     Repair(repair::Opts),
 
+    /// Remove paths from existing snapshots
+    Rewrite(rewrite::Opts),
+
     /// Show general information about the repository
     Repoinfo(repoinfo::Opts),
 
+    /// Show statistics about snapshots or paths within snapshots
+    Stats(stats::Opts),
+
     /// Change tags of snapshots
     Tag(tag::Opts),
 }
@@ -234,6 +257,10 @@ pub fn execute() -> Result<()> {
         return Ok(());
     }
 
+    if let Command::Cache(opts) = args.command {
+        return cache::execute(config, opts);
+    }
+
     let command: String = command
         .into_iter()
         .map(|s| s.to_string_lossy().to_string())
@@ -247,11 +274,23 @@ pub fn execute() -> Result<()> {
         return init::execute(&repo.be, &repo.be_hot, opts, repo.password()?, config_ids);
     }
 
+    if let Command::Repair(opts) = &args.command {
+        if opts.is_hot() {
+            return repair::execute_hot(repo, config);
+        }
+    }
+
     let repo = repo.open()?;
+    if repo.cache.is_some() {
+        if let Err(err) = repo.check_old_caches(config.global.dry_run) {
+            warn!("error checking for old caches: {err}");
+        }
+    }
 
     #[allow(clippy::match_same_arms)]
     match args.command {
         Command::Backup(opts) => backup::execute(repo, config, opts, command)?,
+        Command::Cache(_) => {} // already handled above
         Command::Config(opts) => config::execute(repo, opts)?,
         Command::Cat(opts) => cat::execute(repo, config, opts)?,
         Command::Check(opts) => check::execute(repo, opts)?,
@@ -259,22 +298,32 @@ pub fn execute() -> Result<()> {
         Command::Copy(opts) => copy::execute(repo, config, opts)?,
         Command::Diff(opts) => diff::execute(repo, config, opts)?,
         Command::Dump(opts) => dump::execute(repo, config, opts)?,
+        Command::Find(opts) => find::execute(repo, config, opts)?,
         Command::Forget(opts) => forget::execute(repo, config, opts)?,
         Command::Init(_) => {} // already handled above
         Command::Key(opts) => key::execute(repo, opts)?,
         Command::List(opts) => list::execute(repo, opts)?,
         Command::Ls(opts) => ls::execute(repo, config, opts)?,
         Command::Merge(opts) => merge_cmd::execute(repo, config, opts, command)?,
+        #[cfg(not(windows))]
+        Command::Mount(opts) => mount::execute(repo, config, opts)?,
         Command::SelfUpdate(_) => {} // already handled above
         Command::Snapshots(opts) => snapshots::execute(repo, config, opts)?,
         Command::ShowConfig => {} // already handled above
         Command::Prune(opts) => prune::execute(repo, config, opts, vec![])?,
         Command::Restore(opts) => restore::execute(repo, config, opts)?,
         Command::Repair(opts) => repair::execute(repo, config, opts)?,
+        Command::Rewrite(opts) => rewrite::execute(repo, config, opts, command)?,
         Command::Repoinfo(opts) => repoinfo::execute(repo, opts)?,
+        Command::Stats(opts) => stats::execute(repo, config, opts)?,
         Command::Tag(opts) => tag::execute(repo, config, opts)?,
     };
 
+    let retries = retry_count();
+    if retries > 0 {
+        warn!("{retries} backend requests had to be retried.");
+    }
+
     Ok(())
 }
 
@@ -0,0 +1,524 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use chrono::Local;
+use clap::Parser;
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
+    ReplyXattr, Request,
+};
+use log::*;
+use nix::unistd::{getgid, getuid};
+
+use super::{progress_counter, Config};
+use crate::backend::mapper::map_mode_from_go;
+use crate::blob::{BlobType, Metadata, Node, NodeType, Tree};
+use crate::id::Id;
+use crate::index::{IndexBackend, IndexedBackend};
+use crate::repofile::SnapshotFile;
+use crate::repository::OpenRepository;
+
+#[derive(Parser)]
+pub(super) struct Opts {
+    /// Directory to mount the repository at
+    #[clap(value_name = "PATH")]
+    mountpoint: PathBuf,
+
+    /// Allow other users to access the mounted directory
+    #[clap(long)]
+    allow_other: bool,
+
+    /// Don't let the kernel check access permissions based on the saved file modes
+    #[clap(long)]
+    no_default_permissions: bool,
+
+    /// Number of data blobs to keep in memory for repeated reads
+    #[clap(long, value_name = "COUNT", default_value = "32")]
+    cache_blobs: usize,
+}
+
+pub(super) fn execute(repo: OpenRepository, config: Config, opts: Opts) -> Result<()> {
+    let be = &repo.dbe;
+
+    let mut snapshots = SnapshotFile::all_from_backend(be, &config.snapshot_filter)?;
+    snapshots.sort_unstable();
+    let index = IndexBackend::new(be, progress_counter(""))?;
+    let fs = RusticFs::new(index, &snapshots, opts.cache_blobs);
+
+    let mut options = vec![MountOption::RO, MountOption::FSName("rustic".to_string())];
+    if opts.allow_other {
+        options.push(MountOption::AllowOther);
+    }
+    if !opts.no_default_permissions {
+        options.push(MountOption::DefaultPermissions);
+    }
+
+    info!(
+        "mounting {} snapshot(s) at {:?}. Unmount the directory to quit.",
+        snapshots.len(),
+        opts.mountpoint
+    );
+    fuser::mount2(fs, &opts.mountpoint, &options)?;
+    Ok(())
+}
+
+const TTL: Duration = Duration::from_secs(60);
+const ROOT_INO: u64 = 1;
+const LATEST: &str = "latest";
+
+struct Inode {
+    parent: u64,
+    node: Node,
+    /// entries of a directory; for trees within a snapshot this is only filled when first accessed
+    children: Option<BTreeMap<OsString, u64>>,
+    /// start offsets and ids of the data blobs of a file; this is only filled when first read
+    blobs: Option<Vec<(u64, Id)>>,
+}
+
+/// [`BlobCache`] keeps the last read data blobs in memory
+struct BlobCache {
+    blobs: HashMap<Id, Bytes>,
+    order: VecDeque<Id>,
+    max_blobs: usize,
+}
+
+impl BlobCache {
+    fn new(max_blobs: usize) -> Self {
+        Self {
+            blobs: HashMap::new(),
+            order: VecDeque::new(),
+            max_blobs,
+        }
+    }
+
+    fn get(&mut self, index: &impl IndexedBackend, id: &Id) -> Result<Bytes> {
+        if let Some(data) = self.blobs.get(id) {
+            return Ok(data.clone());
+        }
+
+        let data = index.blob_from_backend(BlobType::Data, id)?;
+        if self.max_blobs > 0 {
+            if self.order.len() >= self.max_blobs {
+                if let Some(old) = self.order.pop_front() {
+                    self.blobs.remove(&old);
+                }
+            }
+            self.order.push_back(*id);
+            self.blobs.insert(*id, data.clone());
+        }
+        Ok(data)
+    }
+}
+
+/// [`RusticFs`] is a read-only FUSE filesystem presenting the snapshots of a repository.
+///
+/// Inodes are allocated when a directory is first listed and are kept for the lifetime of the mount.
+struct RusticFs<I: IndexedBackend> {
+    index: I,
+    inodes: Vec<Inode>,
+    cache: BlobCache,
+    uid: u32,
+    gid: u32,
+}
+
+impl<I: IndexedBackend> RusticFs<I> {
+    fn new(index: I, snapshots: &[SnapshotFile], cache_blobs: usize) -> Self {
+        let mut fs = Self {
+            index,
+            inodes: Vec::new(),
+            cache: BlobCache::new(cache_blobs),
+            uid: getuid().as_raw(),
+            gid: getgid().as_raw(),
+        };
+
+        let root = fs.add_virtual_dir(ROOT_INO, OsStr::new(""));
+        let snapshots_dir = fs.add_virtual_dir(root, OsStr::new("snapshots"));
+        let hosts_dir = fs.add_virtual_dir(root, OsStr::new("hosts"));
+        let tags_dir = fs.add_virtual_dir(root, OsStr::new("tags"));
+
+        // snapshots are sorted by time, so the last one added to a dir is the latest one
+        let mut latest = BTreeMap::new();
+        for sn in snapshots {
+            let mut dirs = vec![snapshots_dir];
+            if !sn.hostname.is_empty() {
+                dirs.push(fs.virtual_subdir(hosts_dir, &sn.hostname));
+            }
+            for tag in sn.tags.iter().filter(|tag| !tag.is_empty()) {
+                dirs.push(fs.virtual_subdir(tags_dir, tag));
+            }
+            for dir in dirs {
+                let name = fs.add_snapshot(dir, sn);
+                latest.insert(dir, name);
+            }
+        }
+
+        for (dir, name) in latest {
+            let node = Node::new_node(
+                OsStr::new(LATEST),
+                NodeType::Symlink {
+                    linktarget: name.to_string_lossy().to_string(),
+                },
+                Metadata::default(),
+            );
+            let ino = fs.add_inode(dir, node, None);
+            fs.insert_child(dir, OsString::from(LATEST), ino);
+        }
+
+        fs
+    }
+
+    fn inode(&self, ino: u64) -> Option<&Inode> {
+        let idx = usize::try_from(ino).ok()?.checked_sub(1)?;
+        self.inodes.get(idx)
+    }
+
+    fn inode_mut(&mut self, ino: u64) -> Option<&mut Inode> {
+        let idx = usize::try_from(ino).ok()?.checked_sub(1)?;
+        self.inodes.get_mut(idx)
+    }
+
+    fn add_inode(
+        &mut self,
+        parent: u64,
+        node: Node,
+        children: Option<BTreeMap<OsString, u64>>,
+    ) -> u64 {
+        self.inodes.push(Inode {
+            parent,
+            node,
+            children,
+            blobs: None,
+        });
+        self.inodes.len() as u64
+    }
+
+    fn insert_child(&mut self, dir: u64, name: OsString, ino: u64) {
+        if let Some(children) = self.inode_mut(dir).and_then(|dir| dir.children.as_mut()) {
+            children.insert(name, ino);
+        }
+    }
+
+    fn add_virtual_dir(&mut self, parent: u64, name: &OsStr) -> u64 {
+        let meta = Metadata {
+            mtime: Some(Local::now()),
+            ..Default::default()
+        };
+        let node = Node::new_node(name, NodeType::Dir, meta);
+        let ino = self.add_inode(parent, node, Some(BTreeMap::new()));
+        if ino != parent {
+            self.insert_child(parent, name.to_os_string(), ino);
+        }
+        ino
+    }
+
+    fn virtual_subdir(&mut self, parent: u64, name: &str) -> u64 {
+        let name = OsString::from(name.replace('/', "_"));
+        let existing = self
+            .inode(parent)
+            .and_then(|dir| dir.children.as_ref())
+            .and_then(|children| children.get(&name).copied());
+        match existing {
+            Some(ino) => ino,
+            None => self.add_virtual_dir(parent, &name),
+        }
+    }
+
+    /// add the root tree of a snapshot to the given dir and return the name used
+    fn add_snapshot(&mut self, dir: u64, sn: &SnapshotFile) -> OsString {
+        let mut name = OsString::from(sn.time.format("%Y-%m-%dT%H:%M:%S%:z").to_string());
+        let exists = self
+            .inode(dir)
+            .and_then(|dir| dir.children.as_ref())
+            .is_some_and(|children| children.contains_key(&name));
+        if exists {
+            name.push(format!("-{}", sn.id));
+        }
+
+        let meta = Metadata {
+            mtime: Some(sn.time),
+            uid: Some(sn.uid),
+            gid: Some(sn.gid),
+            ..Default::default()
+        };
+        let mut node = Node::new_node(&name, NodeType::Dir, meta);
+        node.subtree = Some(sn.tree);
+        let ino = self.add_inode(dir, node, None);
+        self.insert_child(dir, name.clone(), ino);
+        name
+    }
+
+    /// get the entries of the directory `ino`, reading its tree from the backend if needed
+    fn children(&mut self, ino: u64) -> Result<&BTreeMap<OsString, u64>> {
+        let inode = self
+            .inode(ino)
+            .ok_or_else(|| anyhow!("inode {ino} not found"))?;
+        if inode.children.is_none() {
+            let id = inode
+                .node
+                .subtree
+                .ok_or_else(|| anyhow!("inode {ino} is no dir"))?;
+            let tree = Tree::from_backend(&self.index, id)?;
+            let mut children = BTreeMap::new();
+            for node in tree.nodes {
+                let name = node.name();
+                let child = self.add_inode(ino, node, None);
+                children.insert(name, child);
+            }
+            // inode exists as checked above
+            self.inode_mut(ino).unwrap().children = Some(children);
+        }
+        Ok(self.inode(ino).unwrap().children.as_ref().unwrap())
+    }
+
+    fn read_data(&mut self, ino: u64, offset: u64, size: u64) -> Result<Vec<u8>> {
+        let Self {
+            index,
+            inodes,
+            cache,
+            ..
+        } = self;
+        let inode = usize::try_from(ino)
+            .ok()
+            .and_then(|ino| ino.checked_sub(1))
+            .and_then(|idx| inodes.get_mut(idx))
+            .ok_or_else(|| anyhow!("inode {ino} not found"))?;
+
+        if inode.blobs.is_none() {
+            let mut start = 0;
+            let mut blobs = Vec::new();
+            for id in inode.node.content.iter().flatten() {
+                let ie = index
+                    .get_data(id)
+                    .ok_or_else(|| anyhow!("blob {id} not found in index"))?;
+                blobs.push((start, *id));
+                start += u64::from(ie.data_length());
+            }
+            inode.blobs = Some(blobs);
+        }
+        let blobs = inode.blobs.as_ref().unwrap();
+
+        let end = offset + size;
+        let first = blobs
+            .partition_point(|(start, _)| *start <= offset)
+            .saturating_sub(1);
+        let mut data = Vec::new();
+        for (start, id) in &blobs[first..] {
+            if *start >= end {
+                break;
+            }
+            let blob = cache.get(&*index, id)?;
+            let blob_end = start + blob.len() as u64;
+            let from = usize::try_from(offset.max(*start) - start)?;
+            let to = usize::try_from(end.min(blob_end) - start)?;
+            if from < to {
+                data.extend_from_slice(&blob[from..to]);
+            }
+        }
+        Ok(data)
+    }
+
+    fn attr(&self, ino: u64, node: &Node) -> FileAttr {
+        let meta = &node.meta;
+        let (size, rdev) = match &node.node_type {
+            NodeType::File => (meta.size, 0),
+            NodeType::Symlink { linktarget } => (linktarget.len() as u64, 0),
+            NodeType::Dev { device } | NodeType::Chardev { device } => (0, *device),
+            _ => (0, 0),
+        };
+        let perm = match meta.mode {
+            Some(mode) => map_mode_from_go(mode) & 0o7777,
+            None if node.is_dir() => 0o555,
+            None => 0o444,
+        };
+        let nlink = match node.node_type {
+            NodeType::Dir => 2,
+            _ => meta.links.max(1).try_into().unwrap_or(u32::MAX),
+        };
+        let mtime = meta.mtime.map_or(UNIX_EPOCH, SystemTime::from);
+
+        FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: meta.atime.map_or(mtime, SystemTime::from),
+            mtime,
+            ctime: meta.ctime.map_or(mtime, SystemTime::from),
+            crtime: mtime,
+            kind: file_type(node),
+            perm: perm as u16,
+            nlink,
+            uid: meta.uid.unwrap_or(self.uid),
+            gid: meta.gid.unwrap_or(self.gid),
+            rdev: rdev as u32,
+            blksize: 4096,
+            flags: 0,
+        }
+    }
+}
+
+fn file_type(node: &Node) -> FileType {
+    match node.node_type {
+        NodeType::File => FileType::RegularFile,
+        NodeType::Dir => FileType::Directory,
+        NodeType::Symlink { .. } => FileType::Symlink,
+        NodeType::Dev { .. } => FileType::BlockDevice,
+        NodeType::Chardev { .. } => FileType::CharDevice,
+        NodeType::Fifo => FileType::NamedPipe,
+        NodeType::Socket => FileType::Socket,
+    }
+}
+
+fn reply_xattr(reply: ReplyXattr, size: u32, data: &[u8]) {
+    match u32::try_from(data.len()) {
+        Ok(len) if size == 0 => reply.size(len),
+        Ok(len) if len <= size => reply.data(data),
+        _ => reply.error(libc::ERANGE),
+    }
+}
+
+impl<I: IndexedBackend> Filesystem for RusticFs<I> {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self.inode(parent) {
+            None => return reply.error(libc::ENOENT),
+            Some(inode) if !inode.node.is_dir() => return reply.error(libc::ENOTDIR),
+            Some(_) => {}
+        }
+        let ino = match self.children(parent) {
+            Ok(children) => children.get(name).copied(),
+            Err(err) => {
+                warn!("error reading dir: {err}");
+                return reply.error(libc::EIO);
+            }
+        };
+        match ino.and_then(|ino| self.inode(ino).map(|inode| (ino, inode))) {
+            Some((ino, inode)) => reply.entry(&TTL, &self.attr(ino, &inode.node), 0),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.inode(ino) {
+            Some(inode) => reply.attr(&TTL, &self.attr(ino, &inode.node)),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
+        match self.inode(ino).map(|inode| &inode.node.node_type) {
+            Some(NodeType::Symlink { linktarget }) => reply.data(linktarget.as_bytes()),
+            Some(_) => reply.error(libc::EINVAL),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        match self.inode(ino) {
+            None => return reply.error(libc::ENOENT),
+            Some(inode) if inode.node.is_dir() => return reply.error(libc::EISDIR),
+            Some(_) => {}
+        }
+        let offset = match u64::try_from(offset) {
+            Ok(offset) => offset,
+            Err(_) => return reply.error(libc::EINVAL),
+        };
+        match self.read_data(ino, offset, size.into()) {
+            Ok(data) => reply.data(&data),
+            Err(err) => {
+                warn!("error reading file: {err}");
+                reply.error(libc::EIO);
+            }
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let parent = match self.inode(ino) {
+            None => return reply.error(libc::ENOENT),
+            Some(inode) if !inode.node.is_dir() => return reply.error(libc::ENOTDIR),
+            Some(inode) => inode.parent,
+        };
+        let children: Vec<_> = match self.children(ino) {
+            Ok(children) => children
+                .iter()
+                .map(|(name, ino)| (*ino, name.clone()))
+                .collect(),
+            Err(err) => {
+                warn!("error reading dir: {err}");
+                return reply.error(libc::EIO);
+            }
+        };
+
+        let entries = [(ino, OsString::from(".")), (parent, OsString::from(".."))]
+            .into_iter()
+            .chain(children);
+        let skip = usize::try_from(offset).unwrap_or_default();
+        for (i, (ino, name)) in entries.enumerate().skip(skip) {
+            let kind = self
+                .inode(ino)
+                .map_or(FileType::Directory, |inode| file_type(&inode.node));
+            // the offset passed to add() is the offset of the next entry
+            if reply.add(ino, (i + 1) as i64, kind, &name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn getxattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        size: u32,
+        reply: ReplyXattr,
+    ) {
+        let inode = match self.inode(ino) {
+            Some(inode) => inode,
+            None => return reply.error(libc::ENOENT),
+        };
+        match inode
+            .node
+            .meta
+            .extended_attributes
+            .iter()
+            .find(|attr| OsStr::new(&attr.name) == name)
+        {
+            Some(attr) => reply_xattr(reply, size, &attr.value),
+            None => reply.error(libc::ENODATA),
+        }
+    }
+
+    fn listxattr(&mut self, _req: &Request<'_>, ino: u64, size: u32, reply: ReplyXattr) {
+        let inode = match self.inode(ino) {
+            Some(inode) => inode,
+            None => return reply.error(libc::ENOENT),
+        };
+        let mut names = Vec::new();
+        for attr in &inode.node.meta.extended_attributes {
+            names.extend_from_slice(OsStr::new(&attr.name).as_bytes());
+            names.push(0);
+        }
+        reply_xattr(reply, size, &names);
+    }
+}
@@ -1115,7 +1115,7 @@ fn find_used_blobs(
 
     let mut tree_streamer = TreeStreamerOnce::new(index.clone(), snap_trees, p)?;
     while let Some(item) = tree_streamer.next().transpose()? {
-        let (_, tree) = item;
+        let (_, tree, _) = item;
         for node in tree.nodes {
             match node.node_type {
                 NodeType::File => {
@@ -5,8 +5,8 @@ use clap::{Parser, Subcommand};
 use log::*;
 
 use crate::backend::{
-    DecryptFullBackend, DecryptReadBackend, DecryptWriteBackend, FileType, ReadBackend,
-    WriteBackend,
+    DecryptBackend, DecryptFullBackend, DecryptReadBackend, DecryptWriteBackend, FileType,
+    ReadBackend, WriteBackend,
 };
 use crate::blob::{BlobType, NodeType, Packer, Tree};
 use crate::id::Id;
@@ -14,7 +14,7 @@ use crate::index::{IndexBackend, IndexedBackend, Indexer, ReadIndex};
 use crate::repofile::{
     ConfigFile, IndexFile, IndexPack, PackHeader, PackHeaderRef, SnapshotFile, StringList,
 };
-use crate::repository::OpenRepository;
+use crate::repository::{OpenRepository, Repository};
 
 use super::{progress_counter, progress_spinner, warm_up_wait, Config};
 
@@ -30,6 +30,8 @@ enum Command {
     Index(IndexOpts),
     /// Repair snapshots
     Snapshots(SnapOpts),
+    /// Repair the hot repository by synchronizing it with the repository
+    Hot,
 }
 
 #[derive(Default, Parser)]
@@ -58,11 +60,133 @@ struct SnapOpts {
     ids: Vec<String>,
 }
 
+impl Opts {
+    /// `repair hot` needs to be called without opening the hot repository
+    pub(super) fn is_hot(&self) -> bool {
+        matches!(self.command, Command::Hot)
+    }
+}
+
 pub(super) fn execute(repo: OpenRepository, config: Config, opts: Opts) -> Result<()> {
     match opts.command {
         Command::Index(opt) => repair_index(&repo, config, opt),
         Command::Snapshots(opt) => repair_snaps(&repo.dbe, config, opt, &repo.config),
+        Command::Hot => Ok(()), // already handled in execute_hot
+    }
+}
+
+pub(super) fn execute_hot(repo: Repository, config: Config) -> Result<()> {
+    let dry_run = config.global.dry_run;
+    // open the repository without the hot part, as the hot repository may be inconsistent
+    let (repo, hot_be) = repo.split_hot()?;
+    let repo = repo.open()?;
+
+    // the config of the hot repository differs by the is_hot flag, so it cannot be copied
+    let mut hot_dbe = DecryptBackend::new(&hot_be, repo.key.clone());
+    hot_dbe.set_zstd(None);
+    let mut config_file = repo.config.clone();
+    config_file.is_hot = Some(true);
+    let hot_config = match hot_be.list(FileType::Config)?.is_empty() {
+        true => None,
+        false => hot_dbe.get_file::<ConfigFile>(&Id::default()).ok(),
+    };
+    if hot_config.as_ref() != Some(&config_file) {
+        match dry_run {
+            true => info!("would have written config to hot repo"),
+            false => {
+                hot_dbe.save_file(&config_file)?;
+                info!("written config to hot repo");
+            }
+        }
+    }
+
+    for file_type in [FileType::Key, FileType::Snapshot, FileType::Index] {
+        let files = repo.be.list_with_size(file_type)?.into_iter().collect();
+        sync_hot_files(&repo.be, &hot_be, file_type, files, dry_run)?;
+    }
+
+    // the hot repository contains all packs with tree blobs
+    let mut tree_packs = HashMap::new();
+    let p = progress_counter("reading index...");
+    for index in repo.dbe.stream_all::<IndexFile>(p.clone())? {
+        let index = index?.1;
+        for pack in index.packs.into_iter().chain(index.packs_to_delete) {
+            if pack.blob_type() == BlobType::Tree {
+                tree_packs.insert(pack.id, pack.pack_size());
+            }
+        }
     }
+    p.finish();
+
+    let hot_packs: HashMap<_, _> = hot_be.list_with_size(FileType::Pack)?.into_iter().collect();
+    let missing: Vec<_> = tree_packs
+        .iter()
+        .filter(|(id, size)| hot_packs.get(id) != Some(size))
+        .map(|(id, _)| *id)
+        .collect();
+    warm_up_wait(&repo, missing.into_iter(), !dry_run)?;
+    sync_hot_files(&repo.be, &hot_be, FileType::Pack, tree_packs, dry_run)?;
+
+    Ok(())
+}
+
+// copy files which are missing or have a wrong size to the hot repo and remove additional files from it
+fn sync_hot_files(
+    be: &impl ReadBackend,
+    hot_be: &impl WriteBackend,
+    file_type: FileType,
+    mut files: HashMap<Id, u32>,
+    dry_run: bool,
+) -> Result<()> {
+    // packs in the hot repo are tree packs which are cacheable
+    let cacheable = file_type.is_cacheable() || file_type == FileType::Pack;
+    let mut remove = Vec::new();
+    for (id, size_hot) in hot_be.list_with_size(file_type)? {
+        match files.remove(&id) {
+            None => remove.push(id),
+            Some(size) if size != size_hot => {
+                info!("{file_type:?} {id}: hot size {size_hot} differs from size {size}");
+                files.insert(id, size);
+            }
+            Some(_) => {} // everything ok
+        }
+    }
+
+    if dry_run {
+        if !files.is_empty() {
+            info!(
+                "would have copied {} {} files to hot repo",
+                files.len(),
+                file_type.name()
+            );
+        }
+        if !remove.is_empty() {
+            info!(
+                "would have removed {} {} files from hot repo",
+                remove.len(),
+                file_type.name()
+            );
+        }
+        return Ok(());
+    }
+
+    let p = progress_counter(format!("copying {} to hot repo...", file_type.name()));
+    p.set_length(files.len().try_into()?);
+    for id in files.into_keys() {
+        let data = be.read_full(file_type, &id)?;
+        hot_be.write_bytes(file_type, &id, cacheable, data)?;
+        p.inc(1);
+    }
+    p.finish();
+
+    let p = progress_counter(format!("removing {} from hot repo...", file_type.name()));
+    p.set_length(remove.len().try_into()?);
+    for id in remove {
+        hot_be.remove(file_type, &id, cacheable)?;
+        p.inc(1);
+    }
+    p.finish();
+    Ok(())
 }
 
 fn repair_index(repo: &OpenRepository, config: Config, opts: IndexOpts) -> Result<()> {
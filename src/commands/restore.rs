@@ -15,9 +15,9 @@ use super::{bytes, progress_bytes, progress_counter, warm_up_wait, Config};
 use crate::backend::{DecryptReadBackend, FileType, LocalDestination};
 use crate::blob::{Node, NodeStreamer, NodeType, Tree, TreeStreamerOptions};
 use crate::commands::helpers::progress_spinner;
-use crate::crypto::hash;
+use crate::crypto::{hash, zero_id};
 use crate::id::Id;
-use crate::index::{IndexBackend, IndexedBackend};
+use crate::index::{IndexBackend, IndexedBackend, ReadIndex};
 use crate::repofile::{SnapshotFile, SnapshotFilter};
 use crate::repository::OpenRepository;
 
@@ -48,6 +48,10 @@ pub(super) struct Opts {
     #[clap(long)]
     verify_existing: bool,
 
+    /// Don't write blocks of zeros into new files but leave holes (sparse files)
+    #[clap(long)]
+    sparse: bool,
+
     #[clap(flatten)]
     streamer_opts: TreeStreamerOptions,
 
@@ -146,9 +150,11 @@ fn allocate_and_collect(
     let dest_path = Path::new(&opts.dest);
     let mut stats = RestoreStats::default();
 
-    let mut file_infos = FileInfos::new();
+    let mut file_infos = FileInfos::new(opts.sparse);
     let mut additional_existing = false;
     let mut removed_dir = None;
+    // first path of all files with several hard links, identified by device id and inode
+    let mut hardlinks = HashMap::new();
 
     let mut process_existing = |entry: &DirEntry| -> Result<_> {
         if entry.depth() == 0 {
@@ -216,6 +222,23 @@ fn allocate_and_collect(
                 }
             }
             NodeType::File => {
+                if let Some(key) = node.hardlink_key() {
+                    if let Some(original) = hardlinks.get(&key) {
+                        if exists {
+                            stats.file.modify += 1;
+                        } else {
+                            stats.file.restore += 1;
+                        }
+                        debug!("to link: {path:?} -> {original:?}");
+                        if !config.global.dry_run {
+                            dest.create_hardlink(original, path)
+                                .with_context(|| format!("error creating hard link {path:?}"))?;
+                        }
+                        return Ok(());
+                    }
+                    hardlinks.insert(key, path.clone());
+                }
+
                 // collect blobs needed for restoring
                 match (
                     exists,
@@ -235,6 +258,16 @@ fn allocate_and_collect(
                     }
                     // TODO: The differentiation between files to modify and files to create could be done only by add_file
                     // Currently, add_file never returns Modify, but always New, so we differentiate based on exists
+                    (true, AddFileResult::New(size)) if opts.sparse => {
+                        stats.file.modify += 1;
+                        debug!("to modify: {path:?}");
+                        if !config.global.dry_run {
+                            // remove the old contents, as zero blobs are not written
+                            dest.set_length(path, 0)
+                                .and_then(|()| dest.set_length(path, size))
+                                .with_context(|| format!("error setting length for {path:?}"))?;
+                        }
+                    }
                     (true, AddFileResult::New(size) | AddFileResult::Modify(size)) => {
                         stats.file.modify += 1;
                         debug!("to modify: {path:?}");
@@ -461,6 +494,8 @@ struct FileInfos {
     r: RestoreInfo,
     restore_size: u64,
     matched_size: u64,
+    // if set, blobs containing only zeros are not written into new files
+    sparse: bool,
 }
 
 type RestoreInfo = HashMap<Id, HashMap<BlobLocation, Vec<FileLocation>>>;
@@ -498,12 +533,13 @@ enum AddFileResult {
 }
 
 impl FileInfos {
-    fn new() -> Self {
+    fn new(sparse: bool) -> Self {
         Self {
             names: Vec::new(),
             r: HashMap::new(),
             restore_size: 0,
             matched_size: 0,
+            sparse,
         }
     }
 
@@ -514,7 +550,7 @@ impl FileInfos {
         dest: &LocalDestination,
         file: &Node,
         name: PathBuf,
-        index: &impl IndexedBackend,
+        index: &impl ReadIndex,
         ignore_mtime: bool,
     ) -> Result<AddFileResult> {
         let mut open_file = dest.get_matching_file(&name, file.meta.size);
@@ -550,6 +586,12 @@ impl FileInfos {
             };
             let length = bl.data_length();
 
+            if open_file.is_none() && self.sparse && id == &zero_id(length.try_into()?) {
+                // leave a hole in the new file
+                file_pos += length;
+                continue;
+            }
+
             let matches = match &mut open_file {
                 Some(file) => {
                     // Existing file content; check if SHA256 matches
@@ -593,3 +635,84 @@ impl FileInfos {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::blob::{BlobType, Metadata};
+    use crate::index::IndexEntry;
+
+    struct TestIndex(HashMap<Id, IndexEntry>);
+
+    impl ReadIndex for TestIndex {
+        fn get_id(&self, _tpe: BlobType, id: &Id) -> Option<IndexEntry> {
+            self.0.get(id).cloned()
+        }
+        fn total_size(&self, _tpe: BlobType) -> u64 {
+            0
+        }
+        fn has(&self, _tpe: BlobType, id: &Id) -> bool {
+            self.0.contains_key(id)
+        }
+    }
+
+    #[test]
+    fn sparse_restore_skips_zeros() -> Result<()> {
+        let zeros = vec![0; 100_000];
+        let blobs = [&b"start"[..], &zeros[..], &b"end"[..]];
+        let ids: Vec<_> = blobs.iter().map(|blob| hash(blob)).collect();
+        assert_eq!(ids[1], zero_id(zeros.len()));
+
+        let pack = Id::random();
+        let mut offset = 0;
+        let mut entries = HashMap::new();
+        for (id, blob) in ids.iter().zip(blobs) {
+            let length = u32::try_from(blob.len())?;
+            let entry = IndexEntry {
+                blob_type: BlobType::Data,
+                pack,
+                offset,
+                length,
+                uncompressed_length: NonZeroU32::new(length),
+            };
+            let _ = entries.insert(*id, entry);
+            offset += length;
+        }
+        let index = TestIndex(entries);
+
+        let dir = tempfile::tempdir()?;
+        let dest = LocalDestination::new(dir.path().to_str().unwrap(), true, false)?;
+        let size = blobs.iter().map(|blob| blob.len()).sum::<usize>();
+        let meta = Metadata {
+            size: size.try_into()?,
+            ..Default::default()
+        };
+        let mut node = Node::new_node(std::ffi::OsStr::new("file"), NodeType::File, meta);
+        node.content = Some(ids.clone());
+
+        let mut file_infos = FileInfos::new(true);
+        let length = match file_infos.add_file(&dest, &node, "file".into(), &index, false)? {
+            AddFileResult::New(length) => length,
+            _ => panic!("file should be new"),
+        };
+        assert_eq!(length, u64::try_from(size)?);
+
+        // restore like restore_contents does and check that the zeros are not written
+        dest.set_length("file", length)?;
+        let zero_range = 5..5 + zeros.len() as u64;
+        for (bl, fls) in &file_infos.r[&pack] {
+            let blob = &blobs[ids
+                .iter()
+                .position(|id| index.0[id].offset == bl.offset)
+                .unwrap()];
+            for fl in fls {
+                assert!(!zero_range.contains(&fl.file_start));
+                dest.write_at("file", fl.file_start, blob)?;
+            }
+        }
+        assert_eq!(file_infos.r[&pack].len(), 2);
+        assert_eq!(std::fs::read(dir.path().join("file"))?, blobs.concat());
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use chrono::Local;
+use clap::Parser;
+use derive_more::Add;
+use ignore::overrides::Override;
+use ignore::Match;
+use log::*;
+
+use crate::backend::{DecryptWriteBackend, FileType};
+use crate::blob::{BlobType, Packer, Tree, TreeStreamerOptions};
+use crate::id::Id;
+use crate::index::{IndexBackend, IndexedBackend, Indexer, ReadIndex};
+use crate::repofile::{SnapshotFile, SnapshotSummary};
+use crate::repository::OpenRepository;
+
+use super::{progress_counter, Config};
+
+#[derive(Parser)]
+pub(super) struct Opts {
+    /// Snapshots to rewrite. If none is given, use filter options to filter from all snapshots.
+    #[clap(value_name = "ID")]
+    ids: Vec<String>,
+
+    /// Remove the original snapshots after rewriting them
+    #[clap(long)]
+    forget: bool,
+
+    /// Output generated snapshots in json format
+    #[clap(long)]
+    json: bool,
+
+    /// Paths matched by an exclude glob (e.g. --glob '!/home/*/.ssh') are removed from the
+    /// snapshots together with all their contents. Globs without a leading '!' only re-include
+    /// paths excluded by an earlier glob; they never remove anything.
+    #[clap(flatten)]
+    streamer_opts: TreeStreamerOptions,
+}
+
+pub(super) fn execute(
+    repo: OpenRepository,
+    config: Config,
+    opts: Opts,
+    command: String,
+) -> Result<()> {
+    let now = Local::now();
+    let be = &repo.dbe;
+    let dry_run = config.global.dry_run;
+
+    let excludes = opts.streamer_opts.clone().exclude_overrides()?;
+    if excludes.is_empty() {
+        bail!("no exclude glob given, nothing to rewrite");
+    }
+    let overrides = opts.streamer_opts.overrides()?;
+
+    let snapshots = match opts.ids.is_empty() {
+        true => SnapshotFile::all_from_backend(be, &config.snapshot_filter)?,
+        false => SnapshotFile::from_ids(be, &opts.ids)?,
+    };
+
+    let index = IndexBackend::only_full_trees(&be.clone(), progress_counter(""))?;
+    let indexer = Indexer::new(be.clone()).into_shared();
+    let mut rewriter = Rewriter {
+        index: &index,
+        overrides: &overrides,
+        excludes: &excludes,
+        dry_run,
+        rewritten: HashMap::new(),
+    };
+
+    let mut new_snaps = Vec::new();
+    let mut old_snaps = Vec::new();
+    let p = progress_counter("rewriting snapshots...");
+    p.set_length(snapshots.len().try_into()?);
+    for snap in snapshots {
+        let packer = Packer::new(
+            be.clone(),
+            BlobType::Tree,
+            indexer.clone(),
+            &repo.config,
+            index.total_size(BlobType::Tree),
+        )?;
+        let (tree, totals) = rewriter.rewrite_tree(&packer, snap.tree, &mut PathBuf::new())?;
+        let stats = packer.finalize()?;
+        p.inc(1);
+
+        if tree == snap.tree {
+            info!("snapshot {}: nothing to remove.", snap.id);
+            continue;
+        }
+        if dry_run {
+            info!("would have rewritten snapshot {}.", snap.id);
+            continue;
+        }
+
+        let mut summary = SnapshotSummary {
+            command: command.clone(),
+            backup_start: now,
+            ..Default::default()
+        };
+        totals.apply(&mut summary);
+        stats.apply(&mut summary, BlobType::Tree);
+        summary.finalize(now)?;
+
+        let mut new_snap = snap.clone();
+        new_snap.id = Id::default();
+        new_snap.original = Some(snap.original.unwrap_or(snap.id));
+        new_snap.tree = tree;
+        new_snap.summary = Some(summary);
+        new_snaps.push(new_snap);
+        old_snaps.push(snap);
+    }
+    indexer.write().unwrap().finalize()?;
+    p.finish();
+
+    for (new_snap, snap) in new_snaps.iter_mut().zip(&old_snaps) {
+        new_snap.id = be.save_file(new_snap)?;
+        info!("rewrote snapshot {} as {}.", snap.id, new_snap.id);
+    }
+
+    if opts.json {
+        let mut stdout = std::io::stdout();
+        serde_json::to_writer_pretty(&mut stdout, &new_snaps)?;
+    }
+
+    if opts.forget {
+        let now = Local::now();
+        let p = progress_counter("deleting original snapshots...");
+        let snap_ids: Vec<_> = old_snaps
+            .iter()
+            .filter(|sn| !sn.must_keep(now))
+            .map(|sn| &sn.id)
+            .collect();
+        be.delete_list(FileType::Snapshot, true, snap_ids.into_iter(), p)?;
+    }
+
+    Ok(())
+}
+
+/// Totals of the rewritten tree which are needed to recompute the [`SnapshotSummary`]
+#[derive(Default, Clone, Copy, Add)]
+struct Totals {
+    files: u64,
+    dirs: u64,
+    dirs_changed: u64,
+    bytes: u64,
+    dirsize: u64,
+}
+
+impl Totals {
+    fn apply(self, summary: &mut SnapshotSummary) {
+        summary.files_unmodified = self.files;
+        summary.dirs_changed = self.dirs_changed;
+        summary.dirs_unmodified = self.dirs - self.dirs_changed;
+        summary.total_files_processed = self.files;
+        summary.total_dirs_processed = self.dirs;
+        summary.total_bytes_processed = self.bytes;
+        summary.total_dirsize_processed = self.dirsize;
+    }
+}
+
+/// Returns if `path` is matched by an exclude glob and not re-included by a later glob.
+/// Paths which are only ignored because they don't match any include glob are kept.
+fn is_excluded(overrides: &Override, excludes: &Override, path: &Path, is_dir: bool) -> bool {
+    matches!(overrides.matched(path, is_dir), Match::Ignore(_))
+        && matches!(excludes.matched(path, is_dir), Match::Ignore(_))
+}
+
+struct Rewriter<'a, I: IndexedBackend> {
+    index: &'a I,
+    overrides: &'a Override,
+    excludes: &'a Override,
+    dry_run: bool,
+    // as globs depend on the path, a tree can only be reused if it appears at the same path
+    rewritten: HashMap<(PathBuf, Id), (Id, Totals)>,
+}
+
+impl<'a, I: IndexedBackend> Rewriter<'a, I> {
+    /// Remove all nodes matched by an exclude glob from the tree `id` located at `path`.
+    /// Returns the id of the rewritten tree and its totals.
+    fn rewrite_tree(
+        &mut self,
+        packer: &Packer<impl DecryptWriteBackend>,
+        id: Id,
+        path: &mut PathBuf,
+    ) -> Result<(Id, Totals)> {
+        if let Some(result) = self.rewritten.get(&(path.clone(), id)) {
+            return Ok(*result);
+        }
+
+        let mut changed = false;
+        let mut totals = Totals::default();
+        let mut new_tree = Tree::new();
+        for mut node in Tree::from_backend(self.index, id)? {
+            path.push(node.name());
+            if is_excluded(self.overrides, self.excludes, path, node.is_dir()) {
+                info!("removing {path:?}");
+                changed = true;
+                path.pop();
+                continue;
+            }
+
+            if let Some(subtree) = node.subtree {
+                let (new_id, subtotals) = self.rewrite_tree(packer, subtree, path)?;
+                if new_id != subtree {
+                    node.subtree = Some(new_id);
+                    changed = true;
+                }
+                totals = totals + subtotals;
+            } else if node.is_file() {
+                totals.files += 1;
+                totals.bytes += node.meta.size;
+            }
+            path.pop();
+            new_tree.add(node);
+        }
+
+        let (new_id, size) = match changed {
+            true => {
+                let (chunk, new_id) = new_tree.serialize()?;
+                let size = u64::try_from(chunk.len())?;
+                if !self.index.has_tree(&new_id) && !self.dry_run {
+                    packer.add(chunk.into(), new_id)?;
+                }
+                totals.dirs_changed += 1;
+                (new_id, size)
+            }
+            false => {
+                let size = self
+                    .index
+                    .get_tree(&id)
+                    .map_or(0, |ie| u64::from(ie.data_length()));
+                (id, size)
+            }
+        };
+        totals.dirs += 1;
+        totals.dirsize += size;
+
+        self.rewritten.insert((path.clone(), id), (new_id, totals));
+        Ok((new_id, totals))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remaining(globs: &[&str]) -> Result<Vec<&'static str>> {
+        let mut args = vec!["rewrite"];
+        for glob in globs {
+            args.extend(["--glob", glob]);
+        }
+        let opts = TreeStreamerOptions::try_parse_from(args)?;
+        let excludes = opts.clone().exclude_overrides()?;
+        let overrides = opts.overrides()?;
+        let tree = [
+            ("a.log", false),
+            ("b.txt", false),
+            ("dir", true),
+            ("dir/c.log", false),
+            ("dir/d.txt", false),
+        ];
+        // like rewrite_tree, don't descend into removed dirs
+        let mut removed: Vec<&Path> = Vec::new();
+        let mut remaining = Vec::new();
+        for (path, is_dir) in tree {
+            let path = Path::new(path);
+            if removed.iter().any(|dir| path.starts_with(dir)) {
+                continue;
+            }
+            if is_excluded(&overrides, &excludes, path, is_dir) {
+                removed.push(path);
+            } else {
+                remaining.push(path.to_str().unwrap());
+            }
+        }
+        Ok(remaining)
+    }
+
+    #[test]
+    fn rewrite_only_removes_excluded() -> Result<()> {
+        let all = vec!["a.log", "b.txt", "dir", "dir/c.log", "dir/d.txt"];
+        assert_eq!(remaining(&["*.log"])?, all);
+        assert_eq!(remaining(&["!*.log"])?, ["b.txt", "dir", "dir/d.txt"]);
+        // the last matching glob wins
+        assert_eq!(
+            remaining(&["*.log", "!*.log"])?,
+            ["b.txt", "dir", "dir/d.txt"]
+        );
+        assert_eq!(remaining(&["!*.log", "*.log"])?, all);
+        Ok(())
+    }
+
+    #[test]
+    fn rewrite_with_include_and_exclude() -> Result<()> {
+        // remove all .log files except the ones in dir; paths not matched by any glob are kept
+        assert_eq!(
+            remaining(&["!*.log", "/dir/*.log"])?,
+            ["b.txt", "dir", "dir/c.log", "dir/d.txt"]
+        );
+        // remove dir, but not the paths which are only ignored as they don't match the include glob
+        assert_eq!(remaining(&["*.txt", "!/dir"])?, ["a.log", "b.txt"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use clap::{Parser, ValueEnum};
+use derive_more::Add;
+use serde::Serialize;
+
+use super::{bytes, no_progress, progress_counter, table_right_from, Config};
+use crate::blob::{BlobType, Node, NodeStreamer, Tree, TreeStreamerOnce};
+use crate::id::Id;
+use crate::index::{IndexBackend, IndexedBackend, ReadIndex};
+use crate::repofile::{SnapshotFile, SnapshotFilter};
+use crate::repository::OpenRepository;
+
+#[derive(Parser)]
+pub(super) struct Opts {
+    /// Snapshots/paths to show statistics for. If none is given, use filter options to filter from all snapshots
+    #[clap(value_name = "SNAPSHOT[:PATH]")]
+    ids: Vec<String>,
+
+    /// Statistics to compute
+    #[clap(long, value_enum, default_value = "restore-size")]
+    mode: Mode,
+
+    /// Show statistics in json format
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Mode {
+    /// Size of all files which would be written by a restore
+    RestoreSize,
+    /// Size of all blobs referenced, counting each blob only once
+    RawData,
+    /// Size of all blobs of each file, counting blobs only once per file
+    BlobsPerFile,
+    /// Size of all blobs which are only referenced by this snapshot and would be freed by forget + prune
+    Exclusive,
+}
+
+#[derive(Default, Clone, Copy, Add, Serialize)]
+struct Stats {
+    files: u64,
+    dirs: u64,
+    blobs: u64,
+    size: u64,
+    packed_size: u64,
+}
+
+impl Stats {
+    fn add_blob(&mut self, index: &impl ReadIndex, tpe: BlobType, id: &Id) {
+        if let Some(ie) = index.get_id(tpe, id) {
+            self.blobs += 1;
+            self.size += u64::from(ie.data_length());
+            self.packed_size += u64::from(ie.length);
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SnapshotStats {
+    snapshot: Id,
+    path: String,
+    #[serde(flatten)]
+    stats: Stats,
+}
+
+#[derive(Serialize)]
+struct StatsOutput {
+    mode: Mode,
+    snapshots: Vec<SnapshotStats>,
+    total: Stats,
+}
+
+pub(super) fn execute(repo: OpenRepository, config: Config, opts: Opts) -> Result<()> {
+    let be = &repo.dbe;
+
+    let targets = match opts.ids.is_empty() {
+        true => SnapshotFile::all_from_backend(be, &config.snapshot_filter)?
+            .into_iter()
+            .map(|sn| (sn, String::new()))
+            .collect(),
+        false => {
+            let mut targets = Vec::new();
+            for arg in &opts.ids {
+                let (id, path) = arg.split_once(':').unwrap_or((arg, ""));
+                let sn = SnapshotFile::from_str(
+                    be,
+                    id,
+                    |sn| sn.matches(&config.snapshot_filter),
+                    no_progress(),
+                )?;
+                targets.push((sn, path.to_string()));
+            }
+            targets
+        }
+    };
+
+    if opts.mode == Mode::Exclusive && targets.iter().any(|(_, path)| !path.is_empty()) {
+        bail!("mode exclusive only works on whole snapshots, not on paths");
+    }
+
+    let index = match opts.mode {
+        Mode::RestoreSize => IndexBackend::only_full_trees(be, progress_counter(""))?,
+        _ => IndexBackend::new(be, progress_counter(""))?,
+    };
+
+    let (snapshots, total) = match opts.mode {
+        Mode::Exclusive => exclusive_stats(&index, &targets)?,
+        mode => {
+            let mut all_blobs = HashSet::new();
+            let mut snapshots = Vec::new();
+            let mut total = Stats::default();
+            let p = progress_counter("computing statistics...");
+            p.set_length(targets.len() as u64);
+            for (sn, path) in &targets {
+                let node = Tree::node_from_path(&index, sn.tree, Path::new(path))?;
+                let stats = match mode {
+                    Mode::RawData => {
+                        let blobs = referenced_blobs(&index, &node)?;
+                        let mut stats = Stats::default();
+                        for (tpe, id) in &blobs {
+                            stats.add_blob(&index, *tpe, id);
+                        }
+                        all_blobs.extend(blobs);
+                        stats
+                    }
+                    _ => file_stats(&index, &node, mode)?,
+                };
+                total = total + stats;
+                snapshots.push(SnapshotStats {
+                    snapshot: sn.id,
+                    path: path.clone(),
+                    stats,
+                });
+                p.inc(1);
+            }
+            p.finish();
+
+            if mode == Mode::RawData {
+                // blobs referenced by several snapshots are only counted once in total
+                total = Stats::default();
+                for (tpe, id) in &all_blobs {
+                    total.add_blob(&index, *tpe, id);
+                }
+            }
+            (snapshots, total)
+        }
+    };
+
+    if opts.json {
+        let output = StatsOutput {
+            mode: opts.mode,
+            snapshots,
+            total,
+        };
+        let mut stdout = std::io::stdout();
+        serde_json::to_writer_pretty(&mut stdout, &output)?;
+        return Ok(());
+    }
+
+    let titles = match opts.mode {
+        Mode::RestoreSize => ["Snapshot", "Path", "Files", "Dirs", "Restore Size"],
+        Mode::BlobsPerFile => ["Snapshot", "Path", "Files", "Blobs", "Total Size"],
+        Mode::RawData | Mode::Exclusive => [
+            "Snapshot",
+            "Path",
+            "Blobs",
+            "Total Size",
+            "Total Size in Packs",
+        ],
+    };
+    let row = |name: String, path: String, stats: &Stats| match opts.mode {
+        Mode::RestoreSize => [
+            name,
+            path,
+            stats.files.to_string(),
+            stats.dirs.to_string(),
+            bytes(stats.size),
+        ],
+        Mode::BlobsPerFile => [
+            name,
+            path,
+            stats.files.to_string(),
+            stats.blobs.to_string(),
+            bytes(stats.size),
+        ],
+        Mode::RawData | Mode::Exclusive => [
+            name,
+            path,
+            stats.blobs.to_string(),
+            bytes(stats.size),
+            bytes(stats.packed_size),
+        ],
+    };
+
+    let mut table = table_right_from(2, titles);
+    for s in &snapshots {
+        table.add_row(row(
+            s.snapshot.to_string(),
+            format!("/{}", s.path),
+            &s.stats,
+        ));
+    }
+    table.add_row(row("Total".to_string(), String::new(), &total));
+    println!("{table}");
+    if opts.mode == Mode::Exclusive {
+        println!("Total: data which is freed if all given snapshots are removed.");
+    }
+
+    Ok(())
+}
+
+/// Compute file statistics for the given node and everything below it
+fn file_stats(index: &impl IndexedBackend, node: &Node, mode: Mode) -> Result<Stats> {
+    let mut stats = Stats::default();
+    let mut count_node = |node: &Node| {
+        if node.is_dir() {
+            stats.dirs += 1;
+        } else if node.is_file() {
+            stats.files += 1;
+            match mode {
+                Mode::BlobsPerFile => {
+                    let blobs: HashSet<_> = node.content.iter().flatten().collect();
+                    for id in blobs {
+                        stats.add_blob(index, BlobType::Data, id);
+                    }
+                }
+                _ => stats.size += node.meta.size,
+            }
+        }
+    };
+
+    if node.is_dir() {
+        for item in NodeStreamer::new(index.clone(), node)? {
+            let (_, node) = item?;
+            count_node(&node);
+        }
+    } else {
+        count_node(node);
+    }
+    Ok(stats)
+}
+
+/// Get all blobs (including tree blobs) which are referenced by the given node
+fn referenced_blobs(index: &impl IndexedBackend, node: &Node) -> Result<HashSet<(BlobType, Id)>> {
+    let mut blobs: HashSet<_> = node
+        .content
+        .iter()
+        .flatten()
+        .map(|id| (BlobType::Data, *id))
+        .collect();
+
+    if let Some(id) = node.subtree {
+        blobs.insert((BlobType::Tree, id));
+        for item in TreeStreamerOnce::new(index.clone(), vec![id], no_progress())? {
+            let (_, tree, _) = item?;
+            for node in tree.nodes {
+                blobs.extend(
+                    node.content
+                        .iter()
+                        .flatten()
+                        .map(|id| (BlobType::Data, *id)),
+                );
+                if let Some(id) = node.subtree {
+                    blobs.insert((BlobType::Tree, id));
+                }
+            }
+        }
+    }
+    Ok(blobs)
+}
+
+/// Which snapshots reference a blob
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Owner {
+    /// only the given snapshot
+    Snapshot(usize),
+    /// more than one, but only snapshots given on the command line
+    Selected,
+    /// some snapshot which was not given on the command line
+    Other,
+}
+
+impl Owner {
+    fn join(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Other, _) | (_, Self::Other) => Self::Other,
+            (Self::Snapshot(i), Self::Snapshot(j)) if i == j => self,
+            _ => Self::Selected,
+        }
+    }
+}
+
+/// Compute the data which is exclusively referenced by each of the given snapshots.
+/// The total is the data referenced by no other snapshots than the given ones.
+fn exclusive_stats(
+    index: &impl IndexedBackend,
+    targets: &[(SnapshotFile, String)],
+) -> Result<(Vec<SnapshotStats>, Stats)> {
+    let selected: HashMap<_, _> = targets
+        .iter()
+        .enumerate()
+        .map(|(i, (sn, _))| (sn.id, i))
+        .collect();
+
+    let mut owners = HashMap::new();
+    let all_snapshots = SnapshotFile::all_from_backend(index.be(), &SnapshotFilter::default())?;
+    let p = progress_counter("computing exclusive data...");
+    p.set_length(all_snapshots.len() as u64);
+    for sn in &all_snapshots {
+        let owner = match selected.get(&sn.id) {
+            Some(i) => Owner::Snapshot(*i),
+            None => Owner::Other,
+        };
+        mark_tree(index, sn.tree, owner, &mut owners)?;
+        p.inc(1);
+    }
+    p.finish();
+
+    let mut stats = vec![Stats::default(); targets.len()];
+    let mut total = Stats::default();
+    for ((tpe, id), owner) in &owners {
+        match owner {
+            Owner::Snapshot(i) => {
+                stats[*i].add_blob(index, *tpe, id);
+                total.add_blob(index, *tpe, id);
+            }
+            Owner::Selected => total.add_blob(index, *tpe, id),
+            Owner::Other => {}
+        }
+    }
+
+    let snapshots = targets
+        .iter()
+        .zip(stats)
+        .map(|((sn, path), stats)| SnapshotStats {
+            snapshot: sn.id,
+            path: path.clone(),
+            stats,
+        })
+        .collect();
+    Ok((snapshots, total))
+}
+
+/// Mark the tree `id` and all blobs below as being referenced by `owner`.
+/// Subtrees are only visited again if their owner changed, so each tree is read at most three times.
+fn mark_tree(
+    index: &impl IndexedBackend,
+    id: Id,
+    owner: Owner,
+    owners: &mut HashMap<(BlobType, Id), Owner>,
+) -> Result<()> {
+    if !mark(owners, (BlobType::Tree, id), owner) {
+        return Ok(());
+    }
+
+    for node in Tree::from_backend(index, id)?.nodes {
+        for id in node.content.iter().flatten() {
+            mark(owners, (BlobType::Data, *id), owner);
+        }
+        if let Some(id) = node.subtree {
+            mark_tree(index, id, owner, owners)?;
+        }
+    }
+    Ok(())
+}
+
+/// Join the owner of a blob with `owner`. Returns whether the owner changed.
+fn mark(owners: &mut HashMap<(BlobType, Id), Owner>, blob: (BlobType, Id), owner: Owner) -> bool {
+    let new_owner = match owners.get(&blob) {
+        None => owner,
+        Some(old) => old.join(owner),
+    };
+    owners.insert(blob, new_owner) != Some(new_owner)
+}
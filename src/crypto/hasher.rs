@@ -1,7 +1,25 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
 use sha2::{Digest, Sha256};
 
 use crate::id::Id;
 
+lazy_static! {
+    // ids of zero chunks, by length
+    static ref ZERO_IDS: Mutex<HashMap<usize, Id>> = Mutex::new(HashMap::new());
+}
+
 pub fn hash(data: &[u8]) -> Id {
     Id::new(Sha256::digest(data).into())
 }
+
+/// Returns the id of `len` zero bytes. The ids are cached, as zero chunks mostly have the same length.
+pub fn zero_id(len: usize) -> Id {
+    *ZERO_IDS
+        .lock()
+        .unwrap()
+        .entry(len)
+        .or_insert_with(|| hash(&vec![0; len]))
+}
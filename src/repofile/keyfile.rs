@@ -14,9 +14,9 @@ use crate::id::Id;
 #[serde_with::apply(Option => #[serde(default, skip_serializing_if = "Option::is_none")])]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct KeyFile {
-    hostname: Option<String>,
-    username: Option<String>,
-    created: Option<DateTime<Local>>,
+    pub hostname: Option<String>,
+    pub username: Option<String>,
+    pub created: Option<DateTime<Local>>,
     kdf: String,
     #[serde(rename = "N")]
     n: u32,
@@ -141,20 +141,21 @@ fn key_from_backend<B: ReadBackend>(be: &B, id: &Id, passwd: &impl AsRef<[u8]>)
     KeyFile::from_backend(be, id)?.key_from_password(passwd)
 }
 
-/// Find a [`KeyFile`] in the backend that fits to the given password and return the contained key.
+/// Find a [`KeyFile`] in the backend that fits to the given password and return its id
+/// together with the contained key.
 /// If a key hint is given, only this key is tested.
 /// This is recommended for a large number of keys.
 pub fn find_key_in_backend<B: ReadBackend>(
     be: &B,
     passwd: &impl AsRef<[u8]>,
     hint: Option<&Id>,
-) -> Result<Key> {
+) -> Result<(Id, Key)> {
     match hint {
-        Some(id) => key_from_backend(be, id, passwd),
+        Some(id) => Ok((*id, key_from_backend(be, id, passwd)?)),
         None => {
             for id in be.list(FileType::Key)? {
                 if let Ok(key) = key_from_backend(be, &id, passwd) {
-                    return Ok(key);
+                    return Ok((id, key));
                 }
             }
             Err(anyhow!("no suitable key found!"))
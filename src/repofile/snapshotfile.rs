@@ -86,6 +86,10 @@ pub struct SnapshotSummary {
     pub total_duration: f64, // in seconds
 
     pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin_command: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin_command_exit_code: Option<i32>,
     #[derivative(Default(value = "Local::now()"))]
     pub backup_start: DateTime<Local>,
     #[derivative(Default(value = "Local::now()"))]
@@ -656,6 +660,12 @@ impl PathList {
         Ok(paths)
     }
 
+    pub fn from_paths(paths: Vec<PathBuf>) -> Self {
+        let mut paths = PathList(paths);
+        paths.merge_paths();
+        paths
+    }
+
     pub fn len(&self) -> usize {
         self.0.len()
     }
@@ -3,8 +3,11 @@ use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::path::PathBuf;
 use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{bail, Context, Result};
+use bytesize::ByteSize;
 use clap::Parser;
 use log::*;
 use merge::Merge;
@@ -22,11 +25,11 @@ use serde::Deserialize;
 use serde_with::{serde_as, DisplayFromStr};
 
 use crate::backend::{
-    Cache, CachedBackend, ChooseBackend, DecryptBackend, DecryptReadBackend, DecryptWriteBackend,
-    FileType, HotColdBackend, ReadBackend,
+    base_dir, Cache, CacheDir, CachedBackend, ChooseBackend, DecryptBackend, DecryptReadBackend,
+    DecryptWriteBackend, FileType, HotColdBackend, LimitedBackend, Limiter, ReadBackend,
 };
 use crate::crypto::Key;
-use crate::repofile::{find_key_in_backend, ConfigFile};
+use crate::repofile::{find_key_in_backend, ConfigFile, Id};
 
 #[serde_as]
 #[derive(Clone, Default, Debug, Parser, Deserialize, Merge)]
@@ -75,7 +78,27 @@ pub struct RepositoryOptions {
         conflicts_with = "no_cache",
         env = "RUSTIC_CACHE_DIR"
     )]
-    cache_dir: Option<PathBuf>,
+    pub(crate) cache_dir: Option<PathBuf>,
+
+    /// Limit the size of the cache. If the cache grows larger, least recently used files are removed
+    #[clap(long, global = true, conflicts_with = "no_cache", value_name = "SIZE")]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    cache_max_size: Option<ByteSize>,
+
+    /// Also cache the parts of data packs which are read, e.g. by restore or dump
+    #[clap(long, global = true, conflicts_with = "no_cache")]
+    #[merge(strategy = merge::bool::overwrite_false)]
+    cache_data: bool,
+
+    /// Caches of repositories which have not been used for this duration are considered old [default: 30d]
+    #[clap(long, global = true, value_name = "DURATION")]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub(crate) cache_max_age: Option<humantime::Duration>,
+
+    /// Automatically remove old caches of other repositories
+    #[clap(long, global = true, conflicts_with = "no_cache")]
+    #[merge(strategy = merge::bool::overwrite_false)]
+    cleanup_cache: bool,
 
     /// Warm up needed data pack files by only requesting them without processing
     #[clap(long, global = true)]
@@ -91,11 +114,28 @@ pub struct RepositoryOptions {
     #[serde_as(as = "Option<DisplayFromStr>")]
     pub(crate) warm_up_wait: Option<humantime::Duration>,
 
+    /// Limit upload bandwidth to the repository (e.g. 1MiB means 1 MiB/s)
+    #[clap(long, global = true, value_name = "SIZE")]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    limit_upload: Option<ByteSize>,
+
+    /// Limit download bandwidth from the repository (e.g. 10MiB means 10 MiB/s)
+    #[clap(long, global = true, value_name = "SIZE")]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    limit_download: Option<ByteSize>,
+
     #[clap(skip)]
     #[merge(strategy = overwrite)]
     options: HashMap<String, String>,
 }
 
+impl RepositoryOptions {
+    pub fn cache_max_age(&self) -> Duration {
+        self.cache_max_age
+            .map_or(Duration::from_secs(30 * 24 * 60 * 60), |age| *age)
+    }
+}
+
 fn overwrite<T>(left: &mut T, right: T) {
     *left = right;
 }
@@ -135,15 +175,17 @@ fn read_password_from_reader(file: &mut impl BufRead) -> std::io::Result<String>
 
 pub struct Repository {
     pub(crate) name: String,
-    pub(crate) be: HotColdBackend<ChooseBackend>,
-    pub(crate) be_hot: Option<ChooseBackend>,
+    pub(crate) be: HotColdBackend<LimitedBackend<ChooseBackend>>,
+    pub(crate) be_hot: Option<LimitedBackend<ChooseBackend>>,
     pub(crate) opts: RepositoryOptions,
 }
 
 impl Repository {
     pub fn new(opts: RepositoryOptions) -> Result<Self> {
+        // upload and download limits are shared by the hot and cold part of the repository
+        let limiter = Arc::new(Limiter::default());
         let be = match &opts.repository {
-            Some(repo) => ChooseBackend::from_url(repo)?,
+            Some(repo) => LimitedBackend::new(ChooseBackend::from_url(repo)?, limiter.clone()),
             None => bail!("No repository given. Please use the --repository option."),
         };
 
@@ -157,13 +199,25 @@ impl Repository {
         let be_hot = opts
             .repo_hot
             .as_ref()
-            .map(|repo| ChooseBackend::from_url(repo))
+            .map(|repo| -> Result<_> {
+                Ok(LimitedBackend::new(
+                    ChooseBackend::from_url(repo)?,
+                    limiter.clone(),
+                ))
+            })
             .transpose()?;
 
         let mut be = HotColdBackend::new(be, be_hot.clone());
         for (opt, value) in &opts.options {
             be.set_option(opt, value)?;
         }
+        // command line options take precedence over the options from the config file
+        if opts.limit_upload.is_some() {
+            limiter.set_upload(opts.limit_upload);
+        }
+        if opts.limit_download.is_some() {
+            limiter.set_download(opts.limit_download);
+        }
         let mut name = be.location();
         if let Some(be_hot) = &be_hot {
             name.push('#');
@@ -178,6 +232,17 @@ impl Repository {
         })
     }
 
+    /// Split the repository into the repository without hot part and the hot backend
+    pub fn split_hot(self) -> Result<(Self, LimitedBackend<ChooseBackend>)> {
+        let be_hot = match self.be_hot {
+            Some(be_hot) => be_hot,
+            None => bail!("No hot repository given. Please use the --repo-hot option."),
+        };
+        let mut opts = self.opts;
+        opts.repo_hot = None;
+        Ok((Self::new(opts)?, be_hot))
+    }
+
     pub fn password(&self) -> Result<Option<String>> {
         match (
             &self.opts.password,
@@ -248,7 +313,7 @@ impl Repository {
             }
         }
 
-        let key = get_key(&self.be, self.password()?)?;
+        let (key_id, key) = get_key(&self.be, self.password()?)?;
         info!("repository {}: password is correct.", self.name);
 
         let dbe = DecryptBackend::new(&self.be, key.clone());
@@ -261,11 +326,21 @@ impl Repository {
                 _ => {}
             }
         let cache = (!self.opts.no_cache)
-            .then(|| Cache::new(config.id, self.opts.cache_dir.clone()).ok())
+            .then(|| {
+                Cache::new(
+                    config.id,
+                    self.opts.cache_dir.clone(),
+                    self.opts.cache_max_size.map(|size| size.as_u64()),
+                    self.opts.cache_data,
+                )
+                .ok()
+            })
             .flatten();
         match &cache {
             None => info!("using no cache"),
-            Some(cache) => info!("using cache at {}", cache.location()),
+            Some(cache) => {
+                info!("using cache at {}", cache.location());
+            }
         }
         let be_cached = CachedBackend::new(self.be.clone(), cache.clone());
         let mut dbe = DecryptBackend::new(&be_cached, key.clone());
@@ -274,6 +349,7 @@ impl Repository {
 
         Ok(OpenRepository {
             name: self.name,
+            key_id,
             key,
             dbe,
             cache,
@@ -287,17 +363,48 @@ impl Repository {
 
 pub struct OpenRepository {
     pub(crate) name: String,
-    pub(crate) be: HotColdBackend<ChooseBackend>,
-    pub(crate) be_hot: Option<ChooseBackend>,
+    pub(crate) be: HotColdBackend<LimitedBackend<ChooseBackend>>,
+    pub(crate) be_hot: Option<LimitedBackend<ChooseBackend>>,
+    pub(crate) key_id: Id,
     pub(crate) key: Key,
     pub(crate) cache: Option<Cache>,
-    pub(crate) dbe: DecryptBackend<CachedBackend<HotColdBackend<ChooseBackend>>, Key>,
+    pub(crate) dbe:
+        DecryptBackend<CachedBackend<HotColdBackend<LimitedBackend<ChooseBackend>>>, Key>,
     pub(crate) config: ConfigFile,
     pub(crate) opts: RepositoryOptions,
 }
 
+impl OpenRepository {
+    /// Check for old caches of other repositories and remove them if requested.
+    /// With `dry_run`, only show which caches would be removed.
+    pub fn check_old_caches(&self, dry_run: bool) -> Result<()> {
+        let base = base_dir(self.opts.cache_dir.clone())?;
+        let old = CacheDir::list_old(&base, self.opts.cache_max_age())?;
+        if old.is_empty() {
+            return Ok(());
+        }
+        if self.opts.cleanup_cache {
+            for dir in old {
+                match dry_run {
+                    true => info!("would remove old cache {:?}", dir.path),
+                    false => {
+                        dir.remove()?;
+                        info!("removed old cache {:?}", dir.path);
+                    }
+                }
+            }
+        } else {
+            info!(
+                "found {} old caches in {base:?}. Run 'rustic cache --cleanup' to remove them.",
+                old.len()
+            );
+        }
+        Ok(())
+    }
+}
+
 const MAX_PASSWORD_RETRIES: usize = 5;
-pub fn get_key(be: &impl ReadBackend, password: Option<String>) -> Result<Key> {
+pub fn get_key(be: &impl ReadBackend, password: Option<String>) -> Result<(Id, Key)> {
     for _ in 0..MAX_PASSWORD_RETRIES {
         match password {
             // if password is given, directly return the result of find_key_in_backend and don't retry